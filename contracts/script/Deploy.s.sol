@@ -10,9 +10,12 @@ contract CartesiLidoOracleScript is Script {
     function setUp() public {}
 
     function run() public {
+        // the beacon chain genesis time is needed to find block roots, as in the justfile deploy targets
+        uint256 genesisBlockTimestamp = vm.envUint("GENESIS_BLOCK_TIMESTAMP");
+
         vm.startBroadcast();
 
-        oracle = new CartesiLidoOracle(address(0), bytes32(0));
+        oracle = new CartesiLidoOracle(address(0), bytes32(0), genesisBlockTimestamp);
 
         vm.stopBroadcast();
     }
@@ -25,9 +25,11 @@ contract CartesiLidoOracle is CoprocessorAdapter, ISecondOpinionOracle {
     /// @notice Mapping from payload hash to slot of inflight requests.
     mapping(bytes32 => uint256) public inflightRequests;
 
-    constructor(address _taskIssuerAddress, bytes32 _machineHash)
+    constructor(address _taskIssuerAddress, bytes32 _machineHash, uint256 _genesisBlockTimestamp)
         CoprocessorAdapter(_taskIssuerAddress, _machineHash)
-    {}
+    {
+        genesis_block_timestamp = _genesisBlockTimestamp;
+    }
 
     /// @notice Generates a report for a given slot. This slot must be within the last 32768 blocks or this will fail
     function generateReport(uint256 slot, bytes32 preimageRoot) external {
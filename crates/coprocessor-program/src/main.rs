@@ -1,4 +1,4 @@
-mod gio;
+mod preimages;
 
 use std::env;
 use std::error::Error;
@@ -7,17 +7,43 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use alloy_sol_types::SolValue;
-use anyhow::Result;
-use ethereum_consensus::{phase0::SignedBeaconBlockHeader, types::mainnet::BeaconState};
+use anyhow::{ensure, Context as _, Result};
+use ethereum_consensus::{
+    phase0::SignedBeaconBlockHeader,
+    types::mainnet::{BeaconBlockBody, BeaconState},
+};
 use futures_util::FutureExt;
-use gio::get_preimage;
-use io::{derive_report, Input, Manifest, Report};
+use io::{
+    derive_report, execution_payload, AccountProof, Encoding, Input, Manifest, Network,
+    NetworkConfig, OracleReport, PartialStateParser, ReportFieldsParser, SectionKind,
+    StakingModules, StateEncoding,
+};
+use preimages::{find_section, Preimages, DEFAULT_PREFETCH};
 use ssz_rs::prelude::*;
-use tower_cartesi_coprocessor::{listen_http, Request, Response};
+use tower_cartesi_coprocessor::{listen_http, GioClient, Request, Response};
 use tower_service::Service;
 
 type BoxError = Box<dyn Error + Send + Sync>;
 
+/// Network the machine is built for, set by the `NETWORK` build argument in the Dockerfile
+const NETWORK: Option<&str> = option_env!("ORACLE_NETWORK");
+
+/// JSON config for a custom network, set by the `NETWORK_CONFIG` build argument in the Dockerfile
+const NETWORK_CONFIG: Option<&str> = option_env!("ORACLE_NETWORK_CONFIG");
+
+/// The network config built into the machine.
+/// It decides which validators count as Lido's so it must never be taken from the inputs
+fn network_config() -> Result<NetworkConfig> {
+    if let Some(config) = NETWORK_CONFIG.filter(|config| !config.is_empty()) {
+        return Ok(serde_json::from_str(config)?);
+    }
+    NETWORK
+        .unwrap_or("mainnet")
+        .parse::<Network>()?
+        .config()
+        .context("a custom network must be built with a network config")
+}
+
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
     let subscriber = tracing_subscriber::fmt()
@@ -28,14 +54,29 @@ async fn main() -> Result<(), BoxError> {
 
     let server_addr = env::var("ROLLUP_HTTP_SERVER_URL")?;
 
-    let mut app = LidoOracleApp;
+    // number of state chunks to request ahead of the one being processed
+    let prefetch = match env::var("PREIMAGE_PREFETCH") {
+        Ok(prefetch) => prefetch.parse()?,
+        Err(_) => DEFAULT_PREFETCH,
+    };
+
+    let network = network_config()?;
+    tracing::info!("Deriving reports for network {}", network.name);
+
+    let mut app = LidoOracleApp {
+        preimages: Preimages::new(GioClient::new(&server_addr), prefetch),
+        network,
+    };
     tracing::info!("Listening on: {}", server_addr);
     listen_http(&mut app, &server_addr).await?;
 
     Ok(())
 }
 
-struct LidoOracleApp;
+struct LidoOracleApp {
+    preimages: Preimages,
+    network: NetworkConfig,
+}
 
 impl Service<Request> for LidoOracleApp {
     type Response = Response;
@@ -55,13 +96,17 @@ impl Service<Request> for LidoOracleApp {
                     payload
                 );
 
-                async {
-                    let report = run_oracle(payload).await?;
+                let preimages = self.preimages.clone();
+                let network = self.network.clone();
+                async move {
+                    let report = run_oracle(&preimages, &network, payload).await?;
 
                     tracing::info!("Derived report: {:?}", report);
 
                     let mut response = Response::empty_accept();
                     response.add_notice(&report.abi_encode());
+                    response.add_report(&report.balance_breakdown.abi_encode());
+                    response.add_report(&report.staking_modules_abi_encode());
 
                     Ok(response)
                 }
@@ -76,38 +121,130 @@ impl Service<Request> for LidoOracleApp {
 }
 
 /// Perform the input processing, GIO requests and derivation of the report from the state
-async fn run_oracle(input: Vec<u8>) -> Result<Report> {
+async fn run_oracle(
+    preimages: &Preimages,
+    network: &NetworkConfig,
+    input: Vec<u8>,
+) -> Result<OracleReport> {
     let input = Input::abi_decode(&input, true)?;
 
-    let manifest = Manifest::from_bytes(&get_preimage(*input.manifest_hash).await?)?;
+    let manifest = Manifest::from_bytes(&preimages.get(*input.manifest_hash).await?)?;
 
     tracing::debug!("Manifest: {:?}", manifest);
-
-    let block = SignedBeaconBlockHeader::deserialize(&get_preimage(manifest.block_hash).await?)?;
+    ensure!(
+        manifest.network == network.name,
+        "inputs were built for network {} but this machine is built for {}",
+        manifest.network,
+        network.name
+    );
+
+    let block = preimages
+        .load_section(&manifest, SectionKind::BlockHeader, Encoding::Ssz)
+        .await?
+        .context("manifest has no block header section")?;
+    let block = SignedBeaconBlockHeader::deserialize(&block)?;
 
     tracing::debug!("Successfully loaded beacon block: {:?}", block);
 
-    let mut state_bytes = Vec::new();
-    for chunk_hash in manifest.state_chunk_hashes {
-        state_bytes.extend_from_slice(&get_preimage(chunk_hash).await?);
-    }
-    let state = BeaconState::deserialize(&state_bytes)?;
+    let block_body = preimages
+        .load_section(&manifest, SectionKind::BlockBody, Encoding::Ssz)
+        .await?
+        .context("manifest has no block body section")?;
+    let block_body = BeaconBlockBody::deserialize(&block_body)?;
+
+    tracing::debug!("Successfully loaded beacon block body");
 
-    tracing::debug!("Successfully loaded beacon state");
+    // the staking module mapping is committed to by the manifest but can't be verified against
+    // chain data, so the per module counts are emitted as a report rather than in the notice
+    let staking_modules = match preimages
+        .load_section(&manifest, SectionKind::StakingModules, Encoding::Cbor)
+        .await?
+    {
+        Some(bytes) => StakingModules::from_bytes(&bytes)?,
+        None => StakingModules::default(),
+    };
+
+    tracing::debug!("Loaded {} staking modules", staking_modules.modules.len());
 
     // calculate the block root and ensure it matches the input
     tracing::debug!("Calculating block root and checking against input");
-    let block_root = block.hash_tree_root()?;
+    let block_root = block.message.hash_tree_root()?;
     assert_eq!(block_root, *input.block_root);
 
-    // calculate the state root and ensure it is in the block
-    tracing::debug!("Calculating state root and checking state root in block");
-    let state_root = state.hash_tree_root()?;
-    assert_eq!(state_root, block.message.state_root);
+    // calculate the body root and ensure it is in the block
+    tracing::debug!("Calculating body root and checking body root in block");
+    let body_root = block_body.hash_tree_root()?;
+    assert_eq!(body_root, block.message.body_root);
 
-    // now we can trust the data in the state and use it to make a report
-    tracing::debug!("Generating report...");
-    let report = derive_report(&state);
+    // calculate the state root and ensure it is in the block
+    // once checked we can trust the data in the state and use it to make a report
+    let state_section = find_section(&manifest, SectionKind::State, Encoding::Ssz)?
+        .context("manifest has no state section")?;
+    let mut report = match &manifest.state_encoding {
+        StateEncoding::Full => {
+            // the full state cannot be streamed so this holds the whole state in memory.
+            // It grows as chunks arrive rather than trusting the section length to allocate up front
+            let mut state_bytes = Vec::new();
+            preimages
+                .for_each_chunk(&manifest, state_section, |chunk| {
+                    state_bytes.extend(chunk);
+                    Ok(())
+                })
+                .await?;
+            let state = BeaconState::deserialize(&state_bytes)?;
+            tracing::debug!("Successfully loaded beacon state");
+
+            tracing::debug!("Calculating state root and checking state root in block");
+            let state_root = state.hash_tree_root()?;
+            assert_eq!(state_root, block.message.state_root);
+
+            tracing::debug!("Generating report...");
+            derive_report(&state, network, &staking_modules)?
+        }
+        StateEncoding::Partial => {
+            tracing::debug!("Streaming partial beacon state and generating report...");
+            let mut parser = PartialStateParser::new(network, &staking_modules);
+            preimages
+                .for_each_chunk(&manifest, state_section, |chunk| parser.feed(&chunk))
+                .await?;
+            let (state_root, report) = parser.finish()?;
+
+            tracing::debug!("Checking state root in block");
+            assert_eq!(block.message.state_root, state_root);
+            report
+        }
+        StateEncoding::Multiproof {
+            field_count,
+            helpers,
+        } => {
+            tracing::debug!("Streaming beacon state report fields and generating report...");
+            let mut parser = ReportFieldsParser::new(network, &staking_modules);
+            preimages
+                .for_each_chunk(&manifest, state_section, |chunk| parser.feed(&chunk))
+                .await?;
+            let (roots, report) = parser.finish()?;
+
+            tracing::debug!(
+                "Calculating state root from multiproof and checking state root in block"
+            );
+            let state_root = roots.multiproof_root(*field_count, helpers)?;
+            assert_eq!(block.message.state_root, state_root);
+            report
+        }
+    };
+
+    // verify the withdrawal vault account against the execution state root committed to by the block
+    // the section is required, otherwise leaving it out would report a zero balance
+    let proof = preimages
+        .load_section(&manifest, SectionKind::WithdrawalVaultProof, Encoding::Cbor)
+        .await?
+        .context("manifest has no withdrawal vault proof section")?;
+    tracing::debug!("Verifying withdrawal vault account proof");
+    let proof = AccountProof::from_bytes(&proof)?;
+    assert_eq!(proof.address, network.withdrawal_vault);
+    let execution = execution_payload(&block_body)
+        .context("beacon block does not contain an execution payload")?;
+    report.report.withdrawalVaultBalanceWei = proof.verify(execution.state_root)?;
 
     Ok(report)
 }
@@ -0,0 +1,109 @@
+//! Loading the manifest sections via the preimage oracle
+
+use anyhow::{ensure, Result};
+use futures_util::{stream, StreamExt};
+use io::{ChunkIndex, Encoding, IndexRef, Manifest, Section, SectionKind};
+use tower_cartesi_coprocessor::{GioClient, HashType};
+
+/// Number of chunk preimages requested concurrently if not configured
+pub const DEFAULT_PREFETCH: usize = 8;
+
+/// Retrieves preimages, prefetching up to `prefetch` chunks ahead of the one being processed
+#[derive(Debug, Clone)]
+pub struct Preimages {
+    gio: GioClient,
+    prefetch: usize,
+}
+
+impl Preimages {
+    pub fn new(gio: GioClient, prefetch: usize) -> Self {
+        Self {
+            gio,
+            prefetch: prefetch.max(1),
+        }
+    }
+
+    /// Retrieve a single preimage by its keccak256 hash
+    pub async fn get(&self, hash: [u8; 32]) -> Result<Vec<u8>> {
+        Ok(self.gio.get_preimage(HashType::Keccak256, hash).await?)
+    }
+
+    /// Load a section and reassemble its data.
+    /// Returns None if the manifest has no section of this kind
+    pub async fn load_section(
+        &self,
+        manifest: &Manifest,
+        kind: SectionKind,
+        encoding: Encoding,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(section) = find_section(manifest, kind, encoding)? else {
+            return Ok(None);
+        };
+        // the section length comes from the untrusted manifest so the data grows as chunks
+        // arrive rather than being allocated up front
+        let mut data = Vec::new();
+        self.for_each_chunk(manifest, section, |chunk| {
+            data.extend(chunk);
+            Ok(())
+        })
+        .await?;
+        Ok(Some(data))
+    }
+
+    /// Load the chunks of a section and pass each one, decompressed, to `f` in order
+    pub async fn for_each_chunk(
+        &self,
+        manifest: &Manifest,
+        section: &Section,
+        mut f: impl FnMut(Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        // Walk the chunk index depth first. Index nodes are only loaded when reached so at most one
+        // path of nodes is held at a time. The manifest has already validated the root node
+        let mut pending: Vec<IndexRef> = Vec::new();
+        let mut node = section.index.clone();
+        loop {
+            match node {
+                ChunkIndex::Chunks(chunks) => {
+                    // requests run concurrently but results are yielded in order
+                    let mut chunks = stream::iter(chunks)
+                        .map(|chunk| async move {
+                            let preimage = self.get(chunk.hash).await?;
+                            section.compression.decompress(preimage, chunk.length)
+                        })
+                        .buffered(self.prefetch);
+                    while let Some(chunk) = chunks.next().await {
+                        f(chunk?)?;
+                    }
+                }
+                // push in reverse so the children are visited in order
+                ChunkIndex::Nodes(children) => pending.extend(children.into_iter().rev()),
+            }
+            let Some(child) = pending.pop() else {
+                break;
+            };
+            node = ChunkIndex::from_bytes(&self.get(child.hash).await?)?;
+            node.validate(child.length, manifest.max_chunk_size)?;
+        }
+        Ok(())
+    }
+}
+
+/// The section of the given kind, checking it has the expected encoding.
+/// Returns None if the manifest has no section of this kind
+pub fn find_section(
+    manifest: &Manifest,
+    kind: SectionKind,
+    encoding: Encoding,
+) -> Result<Option<&Section>> {
+    let Some(section) = manifest.section(kind) else {
+        return Ok(None);
+    };
+    ensure!(
+        section.encoding == encoding,
+        "{:?} section has encoding {:?}, expected {:?}",
+        kind,
+        section.encoding,
+        encoding
+    );
+    Ok(Some(section))
+}
@@ -0,0 +1,170 @@
+//! Compression of section data before it is chunked
+//!
+//! Decompression uses pure Rust decoders so it runs inside the Cartesi machine.
+//! Compression is left to the orchestrator.
+use std::{fmt::Display, io::Read, str::FromStr};
+
+use anyhow::{anyhow, ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// Compression applied to the data of a section
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// A single zstd frame
+    Zstd,
+    /// Raw (unframed) snappy
+    Snappy,
+}
+
+impl Compression {
+    /// Decompress data that is expected to decompress to exactly `length` bytes
+    pub fn decompress(&self, data: Vec<u8>, length: u64) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => data,
+            Compression::Zstd => {
+                let mut decoder = ruzstd::decoding::StreamingDecoder::new(data.as_slice())
+                    .map_err(|e| anyhow!("invalid zstd frame: {}", e))?;
+                // the length comes from the manifest so it is not trusted to size the allocation
+                let mut decompressed = Vec::new();
+                // read one byte past the expected length so oversized data is detected without decoding all of it
+                (&mut decoder)
+                    .take(length + 1)
+                    .read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            Compression::Snappy => {
+                let decompressed_len = snap::raw::decompress_len(&data)?;
+                ensure!(
+                    decompressed_len as u64 == length,
+                    "snappy data decompresses to {} bytes, expected {}",
+                    decompressed_len,
+                    length
+                );
+                snap::raw::Decoder::new().decompress_vec(&data)?
+            }
+        };
+        ensure!(
+            decompressed.len() as u64 == length,
+            "data decompressed to {} bytes, expected {}",
+            decompressed.len(),
+            length
+        );
+        Ok(decompressed)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "snappy" => Ok(Compression::Snappy),
+            _ => Err(anyhow!("unknown compression: {}", s)),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Snappy => write!(f, "snappy"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compressible data resembling repeated validator records
+    fn data() -> Vec<u8> {
+        (0..64 * 1024u32)
+            .flat_map(|i| [(i % 7) as u8, (i / 1024) as u8, 0, 0])
+            .collect()
+    }
+
+    /// Compress the way the orchestrator does
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Zstd => zstd::encode_all(data, 0).unwrap(),
+            Compression::Snappy => snap::raw::Encoder::new().compress_vec(data).unwrap(),
+        }
+    }
+
+    const ALL: [Compression; 3] = [Compression::None, Compression::Zstd, Compression::Snappy];
+
+    #[test]
+    fn round_trips_each_compression() {
+        let data = data();
+        for compression in ALL {
+            let compressed = compress(compression, &data);
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len() / 4, "{}", compression);
+            }
+            let decompressed = compression
+                .decompress(compressed, data.len() as u64)
+                .unwrap();
+            assert_eq!(decompressed, data, "{}", compression);
+            assert_eq!(
+                compression.to_string().parse::<Compression>().unwrap(),
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        let data = data();
+        for compression in ALL {
+            for length in [data.len() - 1, data.len() + 1] {
+                let compressed = compress(compression, &data);
+                assert!(
+                    compression.decompress(compressed, length as u64).is_err(),
+                    "{} with length {}",
+                    compression,
+                    length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let data = data();
+        for compression in [Compression::Zstd, Compression::Snappy] {
+            let compressed = compress(compression, &data);
+
+            let truncated = compressed[..compressed.len() / 2].to_vec();
+            assert!(
+                compression
+                    .decompress(truncated, data.len() as u64)
+                    .is_err(),
+                "truncated {}",
+                compression
+            );
+
+            let mut bad_header = compressed.clone();
+            bad_header[0] ^= 0xff;
+            bad_header[1] ^= 0xff;
+            assert!(
+                compression
+                    .decompress(bad_header, data.len() as u64)
+                    .is_err(),
+                "bad header {}",
+                compression
+            );
+
+            assert!(compression
+                .decompress(Vec::new(), data.len() as u64)
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Matching of validator withdrawal credentials against those used by Lido
+use alloy_primitives::{Address, B256};
+use serde::{Deserialize, Serialize};
+
+/// Matches the withdrawal credentials of validators belonging to Lido
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialMatcher {
+    /// Matches exactly the given 32 byte withdrawal credentials
+    Exact(B256),
+    /// Matches execution layer credentials that withdraw to the given address.
+    /// Both 0x01 and 0x02 (compounding) credentials match, but not 0x00 BLS credentials
+    Address(Address),
+}
+
+impl CredentialMatcher {
+    pub fn matches(&self, credentials: &[u8]) -> bool {
+        match self {
+            CredentialMatcher::Exact(expected) => credentials == expected.as_slice(),
+            CredentialMatcher::Address(address) => {
+                // execution layer credentials are a prefix byte, 11 zero bytes then the address
+                credentials.len() == 32
+                    && matches!(credentials[0], 0x01 | 0x02)
+                    && credentials[1..12].iter().all(|b| *b == 0)
+                    && &credentials[12..] == address.as_slice()
+            }
+        }
+    }
+}
+
+/// Returns true if any of the matchers match the credentials
+pub fn matches_any(matchers: &[CredentialMatcher], credentials: &[u8]) -> bool {
+    matchers.iter().any(|m| m.matches(credentials))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{credentials, lido_credentials};
+
+    fn vault() -> Address {
+        Address::repeat_byte(0xaa)
+    }
+
+    #[test]
+    fn exact_matches_only_the_given_credentials() {
+        let matcher = CredentialMatcher::Exact(B256::from(lido_credentials()));
+        assert!(matcher.matches(&lido_credentials()));
+
+        let mut compounding = lido_credentials();
+        compounding[0] = 0x02;
+        assert!(!matcher.matches(&compounding));
+        assert!(!matcher.matches(&lido_credentials()[..31]));
+    }
+
+    #[test]
+    fn address_ignores_the_execution_prefix() {
+        let matcher = CredentialMatcher::Address(vault());
+        assert!(matcher.matches(&credentials(0x01, vault())));
+        assert!(matcher.matches(&credentials(0x02, vault())));
+    }
+
+    #[test]
+    fn address_rejects_other_addresses() {
+        let matcher = CredentialMatcher::Address(vault());
+        assert!(!matcher.matches(&credentials(0x01, Address::repeat_byte(0xbb))));
+
+        // the address must follow 11 zero bytes
+        let mut padded = credentials(0x01, vault());
+        padded[1] = 1;
+        assert!(!matcher.matches(&padded));
+        assert!(!matcher.matches(&credentials(0x01, vault())[1..]));
+    }
+
+    #[test]
+    fn address_rejects_bls_credentials() {
+        let matcher = CredentialMatcher::Address(vault());
+        // a 0x00 credential is a hash of a BLS key, even if its bytes end in the address
+        assert!(!matcher.matches(&credentials(0x00, vault())));
+        assert!(!matcher.matches(&credentials(0x03, vault())));
+    }
+
+    #[test]
+    fn matches_any_matcher() {
+        let matchers = [
+            CredentialMatcher::Exact(B256::repeat_byte(1)),
+            CredentialMatcher::Address(vault()),
+        ];
+        assert!(matches_any(&matchers, B256::repeat_byte(1).as_slice()));
+        assert!(matches_any(&matchers, &credentials(0x02, vault())));
+        assert!(!matches_any(&matchers, &credentials(0x00, vault())));
+        assert!(!matches_any(&[], &credentials(0x01, vault())));
+    }
+}
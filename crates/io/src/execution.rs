@@ -0,0 +1,201 @@
+//! Verification of execution layer data anchored to the beacon chain
+use alloy_primitives::{keccak256, Address, Bytes, B256, U256};
+use alloy_trie::{proof::verify_proof, Nibbles, TrieAccount};
+use anyhow::Result;
+use ethereum_consensus::types::mainnet::BeaconBlockBody;
+use serde::{Deserialize, Serialize};
+
+/// The execution block a beacon block commits to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionAnchor {
+    pub block_number: u64,
+    pub state_root: B256,
+}
+
+/// The execution payload included in a beacon block body.
+/// Returns None for blocks before the merge
+pub fn execution_payload(body: &BeaconBlockBody) -> Option<ExecutionAnchor> {
+    macro_rules! anchor {
+        ($body:expr) => {
+            Some(ExecutionAnchor {
+                block_number: $body.execution_payload.block_number,
+                state_root: B256::from_slice($body.execution_payload.state_root.as_slice()),
+            })
+        };
+    }
+    match body {
+        BeaconBlockBody::Phase0(_) | BeaconBlockBody::Altair(_) => None,
+        BeaconBlockBody::Bellatrix(body) => anchor!(body),
+        BeaconBlockBody::Capella(body) => anchor!(body),
+        BeaconBlockBody::Deneb(body) => anchor!(body),
+        BeaconBlockBody::Electra(body) => anchor!(body),
+    }
+}
+
+/// Merkle-Patricia proof of an execution layer account as returned by `eth_getProof`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: u64,
+    pub storage_root: B256,
+    pub code_hash: B256,
+    /// RLP encoded trie nodes from the state root to the account leaf
+    pub proof: Vec<Bytes>,
+}
+
+impl AccountProof {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_cbor::to_vec(self)?)
+    }
+
+    /// Verify the account proof against an execution state root.
+    /// Returns the proven account balance in wei. An account missing from the state fails to
+    /// verify rather than proving a zero balance
+    pub fn verify(&self, state_root: B256) -> Result<U256> {
+        let account = TrieAccount {
+            nonce: self.nonce,
+            balance: self.balance,
+            storage_root: self.storage_root,
+            code_hash: self.code_hash,
+        };
+        let key = Nibbles::unpack(keccak256(self.address));
+        verify_proof(
+            state_root,
+            key,
+            Some(alloy_rlp::encode(account)),
+            &self.proof,
+        )
+        .map_err(|e| anyhow::anyhow!("invalid account proof: {}", e))?;
+        Ok(self.balance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_trie::{proof::ProofRetainer, HashBuilder, EMPTY_ROOT_HASH, KECCAK_EMPTY};
+
+    use super::*;
+
+    fn account(i: u8) -> (Address, TrieAccount) {
+        (
+            Address::repeat_byte(i),
+            TrieAccount {
+                nonce: i as u64,
+                balance: U256::from(i as u64) * U256::from(10u64.pow(18)),
+                storage_root: EMPTY_ROOT_HASH,
+                code_hash: KECCAK_EMPTY,
+            },
+        )
+    }
+
+    /// Build a state trie of accounts 1 to 64 and return its root with the `eth_getProof` style
+    /// proof for `address`, claiming the given account
+    fn prove(address: Address, claimed: TrieAccount) -> (B256, AccountProof) {
+        let target = Nibbles::unpack(keccak256(address));
+        let mut leaves: Vec<(Nibbles, Vec<u8>)> = (1..=64)
+            .map(account)
+            .map(|(address, account)| {
+                (
+                    Nibbles::unpack(keccak256(address)),
+                    alloy_rlp::encode(account),
+                )
+            })
+            .collect();
+        leaves.sort();
+        let mut builder =
+            HashBuilder::default().with_proof_retainer(ProofRetainer::new(vec![target.clone()]));
+        for (key, value) in &leaves {
+            builder.add_leaf(key.clone(), value);
+        }
+        let root = builder.root();
+        let proof = builder
+            .take_proof_nodes()
+            .matching_nodes_sorted(&target)
+            .into_iter()
+            .map(|(_, node)| node)
+            .collect();
+        (
+            root,
+            AccountProof {
+                address,
+                balance: claimed.balance,
+                nonce: claimed.nonce,
+                storage_root: claimed.storage_root,
+                code_hash: claimed.code_hash,
+                proof,
+            },
+        )
+    }
+
+    #[test]
+    fn verifies_a_valid_proof() {
+        let (address, account) = account(42);
+        let balance = account.balance;
+        let (root, proof) = prove(address, account);
+        assert!(proof.proof.len() > 1);
+        assert_eq!(proof.verify(root).unwrap(), balance);
+
+        // the proof survives the CBOR encoding it is passed to the machine in
+        let decoded = AccountProof::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.verify(root).unwrap(), balance);
+    }
+
+    #[test]
+    fn rejects_a_tampered_proof_node() {
+        let (address, account) = account(42);
+        let (root, mut proof) = prove(address, account);
+        for i in 0..proof.proof.len() {
+            let mut tampered = proof.clone();
+            let mut node = tampered.proof[i].to_vec();
+            let last = node.len() - 1;
+            node[last] ^= 1;
+            tampered.proof[i] = node.into();
+            assert!(tampered.verify(root).is_err(), "tampered node {}", i);
+        }
+        proof.proof.pop();
+        assert!(proof.verify(root).is_err());
+    }
+
+    #[test]
+    fn rejects_the_wrong_state_root() {
+        let (address, account) = account(42);
+        let (root, proof) = prove(address, account);
+        let mut wrong = root;
+        wrong.0[0] ^= 1;
+        assert!(proof.verify(wrong).is_err());
+        assert!(proof.verify(EMPTY_ROOT_HASH).is_err());
+    }
+
+    #[test]
+    fn rejects_the_wrong_balance() {
+        let (address, mut account) = account(42);
+        account.balance += U256::from(1);
+        let (root, proof) = prove(address, account);
+        assert!(proof.verify(root).is_err());
+    }
+
+    #[test]
+    fn rejects_an_absent_account() {
+        // an exclusion proof with the empty account eth_getProof returns for a missing address
+        let address = Address::repeat_byte(0xff);
+        let empty = TrieAccount {
+            nonce: 0,
+            balance: U256::ZERO,
+            storage_root: EMPTY_ROOT_HASH,
+            code_hash: KECCAK_EMPTY,
+        };
+        let (root, proof) = prove(address, empty);
+        assert!(!proof.proof.is_empty());
+        assert!(proof.verify(root).is_err());
+
+        // nor can another account's proof be claimed for it
+        let (_, mut proof) = prove(account(42).0, account(42).1);
+        proof.address = address;
+        assert!(proof.verify(root).is_err());
+    }
+}
@@ -1,8 +1,35 @@
-use alloy_primitives::{B256, U256};
+use std::{fmt::Display, str::FromStr};
+
 use alloy_sol_types::sol;
-use anyhow::Result;
-use ethereum_consensus::types::mainnet::BeaconState;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Result};
+
+mod compression;
+mod credentials;
+mod execution;
+mod manifest;
+mod merkle;
+mod multiproof;
+mod network;
+mod partial_state;
+mod report;
+mod staking_modules;
+mod streaming;
+#[cfg(test)]
+mod test_utils;
+
+pub use compression::Compression;
+pub use credentials::{matches_any, CredentialMatcher};
+pub use execution::{execution_payload, AccountProof, ExecutionAnchor};
+pub use manifest::{
+    Chunk, ChunkIndex, Encoding, IndexRef, Manifest, Section, SectionKind, StateEncoding,
+    MANIFEST_VERSION, MAX_INDEX_FANOUT,
+};
+pub use multiproof::build_multiproof;
+pub use network::{ForkSchedule, Network, NetworkConfig};
+pub use partial_state::{field_roots, PartialBeaconState, ReportFieldRoots, ReportFields};
+pub use report::{derive_report, derive_report_from_fields, OracleReport, ReportBuilder};
+pub use staking_modules::{StakingModule, StakingModules};
+pub use streaming::{PartialStateParser, ReportFieldsParser};
 
 sol! {
     /// The input that is passed via the on-chain contract
@@ -17,7 +44,7 @@ sol! {
 sol! {
     /// An oracle report as stored in the contract
     /// This is the output of the coprocessor execution
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     struct Report {
         uint256 clBalanceGwei;
         uint256 withdrawalVaultBalanceWei;
@@ -26,65 +53,75 @@ sol! {
     }
 }
 
-// mainnet
-pub const WITHDRAWAL_CREDENTIALS: B256 = B256::new([
-    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb9, 0xd7, 0x93, 0x48,
-    0x78, 0xb5, 0xfb, 0x96, 0x10, 0xb3, 0xfe, 0x8a, 0x5e, 0x44, 0x1e, 0x8f, 0xad, 0x7e, 0x29, 0x3f,
-]);
-
-// Holesky
-// pub const WITHDRAWAL_CREDENTIALS: B256 = B256::new([
-//     0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x17, 0x9d, 0xEC,
-//     0x45, 0xa3, 0x74, 0x23, 0xEA, 0xD4, 0xFa, 0xD5, 0xfC, 0xb1, 0x36, 0x19, 0x78, 0x72, 0xEA, 0xd9,
-// ]);
-
-pub fn derive_report(state: &BeaconState) -> Report {
-    // total balance of all Lido validators
-    let current_epoch = state.slot() / 32;
-    let (cl_balance_gewi, total_deposited, total_exited) = state
-        .validators()
-        .iter()
-        .zip(state.balances().iter())
-        .filter(|(v, _)| v.withdrawal_credentials.as_slice() == WITHDRAWAL_CREDENTIALS.as_slice())
-        .fold(
-            (0u64, 0u64, 0u64),
-            |(cl_balance_gwei, total_deposited, total_exited), (validator, bal)| {
-                let did_exit = if validator.exit_epoch <= current_epoch {
-                    1
-                } else {
-                    0
-                };
-                (
-                    cl_balance_gwei + bal,
-                    total_deposited + 1,
-                    total_exited + did_exit,
-                )
-            },
-        );
+sol! {
+    /// Validator counts for a single Lido staking module.
+    /// These are derived from the mapping committed to by the manifest, which is not checked
+    /// against chain data, so they are emitted as a Cartesi report alongside the notice and are
+    /// never part of the onchain report
+    #[derive(Debug, Clone)]
+    struct StakingModuleReport {
+        uint256 moduleId;
+        uint256 totalDepositedValidators;
+        uint256 totalExitedValidators;
+    }
+}
 
-    Report {
-        clBalanceGwei: U256::from(cl_balance_gewi),
-        withdrawalVaultBalanceWei: U256::ZERO, // TODO: This requires execute state data so is a problem for another time
-        totalDepositedValidators: U256::from(total_deposited),
-        totalExitedValidators: U256::from(total_exited),
+sol! {
+    /// How `clBalanceGwei` was derived. This is emitted as a Cartesi report alongside the notice.
+    ///
+    /// Rules applied:
+    /// - validatorBalancesGwei: Sum of the balances of all validators with Lido withdrawal credentials.
+    /// - pendingDepositsGwei (Electra+): Pending deposits that will be credited to a Lido validator.
+    ///   A deposit for an existing validator counts if that validator is Lido's. A deposit for a new
+    ///   pubkey counts if the first pending deposit for that pubkey has Lido withdrawal credentials.
+    ///   Deposit signatures are not verified, so an invalidly signed first deposit, which the beacon
+    ///   chain would drop, can misattribute the deposits for a new pubkey. The error is at most the
+    ///   pending deposits to new pubkeys. This IS included in clBalanceGwei.
+    /// - pendingPartialWithdrawalsGwei (Electra+): Partial withdrawals queued from Lido validators.
+    ///   These are still held by the validators until processed so are NOT subtracted from clBalanceGwei.
+    /// - pendingConsolidationsOutGwei/InGwei (Electra+): Balance of consolidation sources moving from Lido
+    ///   to non-Lido validators and vice versa. Balance is attributed to the validator currently holding it
+    ///   so these are NOT applied to clBalanceGwei. Consolidations between two Lido validators are ignored.
+    #[derive(Debug, Clone)]
+    struct BalanceBreakdown {
+        uint256 validatorBalancesGwei;
+        uint256 pendingDepositsGwei;
+        uint256 pendingPartialWithdrawalsGwei;
+        uint256 pendingConsolidationsOutGwei;
+        uint256 pendingConsolidationsInGwei;
     }
 }
 
-/// THe manifest is the first piece of data loaded into the coprocessor
-/// It contains the block hash and the state chunk hashes which can be used to
-/// retrieve the content via the preimage oracle
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Manifest {
-    pub block_hash: [u8; 32],
-    pub state_chunk_hashes: Vec<[u8; 32]>,
+/// Format selected by the orchestrator for loading the beacon state into the coprocessor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateFormat {
+    /// The full SSZ encoded BeaconState
+    Full,
+    /// SSZ encoded [PartialBeaconState], keeping only the fields required for the report
+    Partial,
+    /// SSZ encoded [ReportFields] with a multiproof against the state root
+    Multiproof,
 }
 
-impl Manifest {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        Ok(serde_cbor::from_slice(bytes)?)
+impl FromStr for StateFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "full" => Ok(StateFormat::Full),
+            "partial" => Ok(StateFormat::Partial),
+            "multiproof" => Ok(StateFormat::Multiproof),
+            _ => Err(anyhow!("unknown state format: {}", s)),
+        }
     }
+}
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>> {
-        Ok(serde_cbor::to_vec(self)?)
+impl Display for StateFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateFormat::Full => write!(f, "full"),
+            StateFormat::Partial => write!(f, "partial"),
+            StateFormat::Multiproof => write!(f, "multiproof"),
+        }
     }
 }
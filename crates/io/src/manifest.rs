@@ -0,0 +1,320 @@
+//! The manifest is the first piece of data loaded into the coprocessor
+//!
+//! It is versioned and self-describing: it lists every data section the program must load
+//! along with how that section is encoded and the hashes of the chunks it was split into.
+//! Chunk boundaries are content defined so consecutive reports share most of their chunks.
+//! Large sections list their chunks in a tree of index nodes, each its own preimage, so the
+//! manifest stays the same size however large the state grows.
+//! A machine refuses a manifest version it does not know rather than misreading it.
+use alloy_primitives::keccak256;
+use anyhow::{anyhow, bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Compression;
+
+/// Version of the manifest layout produced and accepted by this crate.
+/// Version 1 was the original unversioned layout
+pub const MANIFEST_VERSION: u32 = 8;
+
+/// Maximum number of entries in a [ChunkIndex] node.
+/// This bounds the size of the manifest and of every index node preimage
+pub const MAX_INDEX_FANOUT: usize = 1024;
+
+/// How the beacon state is encoded in the state section
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateEncoding {
+    /// The full SSZ encoded BeaconState
+    Full,
+    /// SSZ encoded [PartialBeaconState](crate::PartialBeaconState)
+    Partial,
+    /// SSZ encoded [ReportFields](crate::ReportFields). These are proven against the state root
+    /// with the helper nodes of a multiproof over the `field_count` BeaconState fields
+    Multiproof {
+        field_count: usize,
+        helpers: Vec<[u8; 32]>,
+    },
+}
+
+/// The data held in a section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    /// The SignedBeaconBlockHeader whose root is the coprocessor input
+    BlockHeader,
+    /// The BeaconBlockBody committed to by the header
+    BlockBody,
+    /// The beacon state in the [StateEncoding] given by the manifest
+    State,
+    /// The [StakingModules](crate::StakingModules) mapping the per module counts are derived from.
+    /// Optional, without it no per module counts are emitted
+    StakingModules,
+    /// The [AccountProof](crate::AccountProof) of the withdrawal vault. Required by the coprocessor
+    WithdrawalVaultProof,
+}
+
+/// Serialization of the data in a section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Ssz,
+    Cbor,
+}
+
+/// A chunk of section data retrieved via the preimage oracle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Keccak256 hash of the (possibly compressed) chunk preimage
+    pub hash: [u8; 32],
+    /// Length in bytes of the chunk data once decompressed
+    pub length: u64,
+}
+
+/// Reference to a child [ChunkIndex] node stored as a CBOR preimage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexRef {
+    /// Keccak256 hash of the node preimage
+    pub hash: [u8; 32],
+    /// Total decompressed length of the chunks under the node
+    pub length: u64,
+}
+
+/// A node in the tree listing the chunks of a section, in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkIndex {
+    /// A leaf listing the chunks themselves
+    Chunks(Vec<Chunk>),
+    /// An interior node listing child nodes
+    Nodes(Vec<IndexRef>),
+}
+
+impl ChunkIndex {
+    /// Build the index tree for a list of chunks.
+    /// Returns the root node along with the preimages of all the other nodes
+    pub fn build(chunks: Vec<Chunk>) -> Result<(Self, Vec<Vec<u8>>)> {
+        Self::build_with_fanout(chunks, MAX_INDEX_FANOUT)
+    }
+
+    fn build_with_fanout(chunks: Vec<Chunk>, fanout: usize) -> Result<(Self, Vec<Vec<u8>>)> {
+        let mut preimages = Vec::new();
+        let mut node = ChunkIndex::Chunks(chunks);
+        while node.len() > fanout {
+            let children = match &node {
+                ChunkIndex::Chunks(chunks) => chunks
+                    .chunks(fanout)
+                    .map(|c| ChunkIndex::Chunks(c.to_vec()))
+                    .collect::<Vec<_>>(),
+                ChunkIndex::Nodes(refs) => refs
+                    .chunks(fanout)
+                    .map(|r| ChunkIndex::Nodes(r.to_vec()))
+                    .collect(),
+            };
+            let mut refs = Vec::with_capacity(children.len());
+            for child in children {
+                let bytes = child.to_bytes()?;
+                refs.push(IndexRef {
+                    hash: keccak256(&bytes).0,
+                    length: child.length(),
+                });
+                preimages.push(bytes);
+            }
+            node = ChunkIndex::Nodes(refs);
+        }
+        Ok((node, preimages))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_cbor::to_vec(self)?)
+    }
+
+    /// Number of entries in this node
+    pub fn len(&self) -> usize {
+        match self {
+            ChunkIndex::Chunks(chunks) => chunks.len(),
+            ChunkIndex::Nodes(refs) => refs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total decompressed length of the chunks under this node
+    pub fn length(&self) -> u64 {
+        match self {
+            ChunkIndex::Chunks(chunks) => chunks.iter().map(|c| c.length).sum(),
+            ChunkIndex::Nodes(refs) => refs.iter().map(|r| r.length).sum(),
+        }
+    }
+
+    /// Check this node is within the fanout, covers `length` bytes and its chunks are within the
+    /// maximum chunk size. Child nodes are checked as they are loaded
+    pub fn validate(&self, length: u64, max_chunk_size: u64) -> Result<()> {
+        ensure!(
+            self.len() <= MAX_INDEX_FANOUT,
+            "chunk index node has {} entries, the maximum is {}",
+            self.len(),
+            MAX_INDEX_FANOUT
+        );
+        if let ChunkIndex::Chunks(chunks) = self {
+            ensure!(
+                chunks
+                    .iter()
+                    .all(|c| c.length > 0 && c.length <= max_chunk_size),
+                "chunk index has a chunk that is empty or larger than the maximum chunk size"
+            );
+        }
+        ensure!(
+            self.length() == length,
+            "chunk index node covers {} bytes, expected {}",
+            self.length(),
+            length
+        );
+        Ok(())
+    }
+}
+
+/// A piece of data split into chunks that are each retrieved via the preimage oracle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub encoding: Encoding,
+    /// Compression applied to each chunk independently.
+    /// Compressing after chunking keeps identical data in identical preimages
+    pub compression: Compression,
+    /// Length in bytes of the encoded data
+    pub length: u64,
+    /// Root of the tree listing the chunks in order
+    pub index: ChunkIndex,
+}
+
+/// Commits to all the data the report is derived from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Maximum decompressed length of a chunk
+    pub max_chunk_size: u64,
+    /// Sum of the lengths of all sections
+    pub total_length: u64,
+    /// Name of the network the inputs were built for. The machine only uses this to reject inputs
+    /// for another network, the config itself is built into the machine
+    pub network: String,
+    pub state_encoding: StateEncoding,
+    pub sections: Vec<Section>,
+}
+
+/// Just the version of a manifest, so it can be checked before decoding the rest
+#[derive(Deserialize)]
+struct VersionTag {
+    version: Option<u32>,
+}
+
+impl Manifest {
+    pub fn new(max_chunk_size: u64, network: String, state_encoding: StateEncoding) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            max_chunk_size,
+            total_length: 0,
+            network,
+            state_encoding,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn add_section(&mut self, section: Section) {
+        self.total_length += section.length;
+        self.sections.push(section);
+    }
+
+    /// The section of the given kind, if the manifest has one
+    pub fn section(&self, kind: SectionKind) -> Option<&Section> {
+        self.sections.iter().find(|s| s.kind == kind)
+    }
+
+    /// Decode a manifest, rejecting any version other than [MANIFEST_VERSION]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let tag: VersionTag = serde_cbor::from_slice(bytes)
+            .map_err(|e| anyhow!("manifest is not a valid CBOR map: {}", e))?;
+        match tag.version {
+            Some(MANIFEST_VERSION) => {}
+            Some(version) => bail!(
+                "unsupported manifest version {} (this build supports version {})",
+                version,
+                MANIFEST_VERSION
+            ),
+            None => bail!(
+                "unversioned (version 1) manifests are not supported, rebuild the inputs with a current orchestrator"
+            ),
+        }
+
+        let manifest: Self = serde_cbor::from_slice(bytes)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_cbor::to_vec(self)?)
+    }
+
+    /// Check the section metadata is consistent
+    fn validate(&self) -> Result<()> {
+        let mut total_length = 0u64;
+        for (i, section) in self.sections.iter().enumerate() {
+            ensure!(
+                self.sections[..i].iter().all(|s| s.kind != section.kind),
+                "manifest has more than one {:?} section",
+                section.kind
+            );
+            section
+                .index
+                .validate(section.length, self.max_chunk_size)?;
+            total_length += section.length;
+        }
+        ensure!(
+            total_length == self.total_length,
+            "manifest total length {} does not match the sum of its sections {}",
+            self.total_length,
+            total_length
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [MANIFEST_VERSION - 1, MANIFEST_VERSION + 1, 0] {
+            let mut manifest =
+                Manifest::new(MAX_CHUNK_SIZE, "mainnet".to_string(), StateEncoding::Full);
+            manifest.version = version;
+            let err = Manifest::from_bytes(&manifest.to_bytes().unwrap()).unwrap_err();
+            assert!(
+                err.to_string().contains("unsupported manifest version"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unversioned_manifests() {
+        // the original layout had no version field
+        #[derive(Serialize)]
+        struct Unversioned {
+            chunks: Vec<[u8; 32]>,
+            total_length: u64,
+        }
+        let bytes = serde_cbor::to_vec(&Unversioned {
+            chunks: vec![[1; 32]],
+            total_length: 1,
+        })
+        .unwrap();
+        let err = Manifest::from_bytes(&bytes).unwrap_err();
+        assert!(err.to_string().contains("unversioned"), "{}", err);
+
+        assert!(Manifest::from_bytes(&serde_cbor::to_vec(&[1, 2, 3]).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Minimal SSZ merkleization helpers for recomputing roots from pre-hashed leaves
+use sha2::{Digest, Sha256};
+
+pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Merkleize the leaves, padding with zero leaves up to the next power of two
+pub fn merkleize(leaves: &[[u8; 32]]) -> [u8; 32] {
+    let width = leaves.len().next_power_of_two();
+    let mut layer: Vec<[u8; 32]> = leaves.to_vec();
+    layer.resize(width, [0u8; 32]);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer[0]
+}
+
+/// Root of a tree of the given depth with all zero leaves
+pub fn zero_hash(depth: u32) -> [u8; 32] {
+    let mut node = [0u8; 32];
+    for _ in 0..depth {
+        node = hash_pair(&node, &node);
+    }
+    node
+}
+
+/// Mix the length of a list into the root of its data as SSZ does for lists
+pub fn mix_in_length(root: &[u8; 32], length: u64) -> [u8; 32] {
+    let mut length_chunk = [0u8; 32];
+    length_chunk[..8].copy_from_slice(&length.to_le_bytes());
+    hash_pair(root, &length_chunk)
+}
+
+/// Merkleizes leaves one at a time into a tree of fixed depth, holding only one node per level.
+/// This is the same algorithm as the deposit contract
+pub struct IncrementalMerkleizer {
+    depth: u32,
+    count: u64,
+    /// Left siblings of the path to the next leaf
+    branch: Vec<[u8; 32]>,
+    zero_hashes: Vec<[u8; 32]>,
+}
+
+impl IncrementalMerkleizer {
+    pub fn new(depth: u32) -> Self {
+        Self {
+            depth,
+            count: 0,
+            branch: vec![[0u8; 32]; depth as usize],
+            zero_hashes: (0..depth).map(zero_hash).collect(),
+        }
+    }
+
+    /// Number of leaves pushed
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn push(&mut self, leaf: [u8; 32]) {
+        assert!(
+            self.count < (1u64 << self.depth),
+            "merkle tree of depth {} is full",
+            self.depth
+        );
+        self.count += 1;
+        let mut size = self.count;
+        let mut node = leaf;
+        for height in 0..self.depth as usize {
+            if size & 1 == 1 {
+                self.branch[height] = node;
+                return;
+            }
+            node = hash_pair(&self.branch[height], &node);
+            size >>= 1;
+        }
+    }
+
+    /// Root of the tree with all remaining leaves set to zero
+    pub fn root(&self) -> [u8; 32] {
+        let mut node = [0u8; 32];
+        let mut size = self.count;
+        for height in 0..self.depth as usize {
+            node = if size & 1 == 1 {
+                hash_pair(&self.branch[height], &node)
+            } else {
+                hash_pair(&node, &self.zero_hashes[height])
+            };
+            size >>= 1;
+        }
+        node
+    }
+}
@@ -0,0 +1,192 @@
+//! SSZ multiproofs of the report fields against the beacon state root
+//!
+//! Follows the multiproof construction from the consensus specs
+//! <https://github.com/ethereum/consensus-specs/blob/dev/ssz/merkle-proofs.md#merkle-multiproofs>
+//! applied to the tree of BeaconState field roots.
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, ensure, Result};
+
+use crate::{
+    merkle::hash_pair,
+    partial_state::{ReportFieldRoots, ReportFields},
+};
+
+fn get_branch_indices(tree_index: usize) -> Vec<usize> {
+    let mut branch = vec![tree_index ^ 1];
+    while *branch.last().unwrap() > 1 {
+        let next = (branch.last().unwrap() / 2) ^ 1;
+        branch.push(next);
+    }
+    branch.pop();
+    branch
+}
+
+fn get_path_indices(tree_index: usize) -> Vec<usize> {
+    let mut path = vec![tree_index];
+    while *path.last().unwrap() > 1 {
+        path.push(path.last().unwrap() / 2);
+    }
+    path.pop();
+    path
+}
+
+/// Indices of the nodes required to prove the given generalized indices, in descending order
+pub fn get_helper_indices(indices: &[usize]) -> Vec<usize> {
+    let mut helpers = BTreeSet::new();
+    let mut paths = BTreeSet::new();
+    for index in indices {
+        helpers.extend(get_branch_indices(*index));
+        paths.extend(get_path_indices(*index));
+    }
+    helpers.difference(&paths).rev().copied().collect()
+}
+
+/// Compute the root from the proven leaves and the helper nodes
+pub fn calculate_multi_merkle_root(
+    leaves: &[[u8; 32]],
+    proof: &[[u8; 32]],
+    indices: &[usize],
+) -> Result<[u8; 32]> {
+    ensure!(leaves.len() == indices.len(), "leaf count mismatch");
+    let helper_indices = get_helper_indices(indices);
+    ensure!(proof.len() == helper_indices.len(), "proof length mismatch");
+
+    let mut objects: HashMap<usize, [u8; 32]> = indices
+        .iter()
+        .copied()
+        .zip(leaves.iter().copied())
+        .chain(helper_indices.iter().copied().zip(proof.iter().copied()))
+        .collect();
+    let mut keys: Vec<usize> = objects.keys().copied().collect();
+    keys.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut pos = 0;
+    while pos < keys.len() {
+        let k = keys[pos];
+        if objects.contains_key(&k)
+            && objects.contains_key(&(k ^ 1))
+            && !objects.contains_key(&(k / 2))
+        {
+            let parent = hash_pair(&objects[&((k | 1) ^ 1)], &objects[&(k | 1)]);
+            objects.insert(k / 2, parent);
+            keys.push(k / 2);
+        }
+        pos += 1;
+    }
+    objects
+        .get(&1)
+        .copied()
+        .ok_or_else(|| anyhow!("multiproof does not reach the root"))
+}
+
+/// Generalized indices of the report fields in a state with `field_count` fields
+pub fn report_field_indices(field_count: usize) -> Vec<usize> {
+    let width = field_count.next_power_of_two();
+    ReportFields::field_indices(field_count)
+        .into_iter()
+        .map(|field| width + field)
+        .collect()
+}
+
+/// Build the helper nodes proving the report fields given the roots of every state field
+pub fn build_multiproof(field_roots: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    let width = field_roots.len().next_power_of_two();
+    let mut tree = vec![[0u8; 32]; 2 * width];
+    tree[width..width + field_roots.len()].copy_from_slice(field_roots);
+    for i in (1..width).rev() {
+        tree[i] = hash_pair(&tree[2 * i], &tree[2 * i + 1]);
+    }
+    get_helper_indices(&report_field_indices(field_roots.len()))
+        .into_iter()
+        .map(|index| tree[index])
+        .collect()
+}
+
+impl ReportFieldRoots {
+    /// Compute the state root from the report field roots and a multiproof of the remaining tree
+    pub fn multiproof_root(&self, field_count: usize, helpers: &[[u8; 32]]) -> Result<[u8; 32]> {
+        let mut roots = vec![[0u8; 32]; field_count];
+        self.insert_into(&mut roots)?;
+        let leaves: Vec<[u8; 32]> = ReportFields::field_indices(field_count)
+            .into_iter()
+            .map(|field| roots[field])
+            .collect();
+        calculate_multi_merkle_root(&leaves, helpers, &report_field_indices(field_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssz_rs::prelude::*;
+
+    use super::*;
+    use crate::{partial_state::field_roots, test_utils::*};
+
+    #[test]
+    fn helper_indices_match_the_spec_example() {
+        // proving 9 and 14 in a tree of depth 3 needs the siblings along both paths
+        assert_eq!(get_helper_indices(&[9, 14]), vec![15, 8, 6, 5]);
+    }
+
+    #[test]
+    fn multiproof_root_matches_state_root() {
+        let state = electra_state();
+        let roots = field_roots(&state).unwrap();
+        let helpers = build_multiproof(&roots);
+        let fields = ReportFields::from_state(&state).unwrap();
+
+        let root = fields
+            .roots()
+            .unwrap()
+            .multiproof_root(roots.len(), &helpers)
+            .unwrap();
+        assert_eq!(root, state.hash_tree_root().unwrap().0);
+    }
+
+    #[test]
+    fn tampered_helper_is_rejected() {
+        let state = electra_state();
+        let roots = field_roots(&state).unwrap();
+        let fields = ReportFields::from_state(&state).unwrap().roots().unwrap();
+        let state_root = state.hash_tree_root().unwrap().0;
+
+        let helpers = build_multiproof(&roots);
+        for i in 0..helpers.len() {
+            let mut tampered = helpers.clone();
+            tampered[i][0] ^= 1;
+            let root = fields.multiproof_root(roots.len(), &tampered).unwrap();
+            assert_ne!(root, state_root, "helper {} was not checked", i);
+        }
+
+        let mut short = helpers.clone();
+        short.pop();
+        assert!(fields.multiproof_root(roots.len(), &short).is_err());
+    }
+
+    #[test]
+    fn tampered_leaf_is_rejected() {
+        let state = electra_state();
+        let roots = field_roots(&state).unwrap();
+        let helpers = build_multiproof(&roots);
+        let state_root = state.hash_tree_root().unwrap().0;
+
+        let mut fields = ReportFields::from_state(&state).unwrap();
+        fields.balances[0] += 1;
+        let root = fields
+            .roots()
+            .unwrap()
+            .multiproof_root(roots.len(), &helpers)
+            .unwrap();
+        assert_ne!(root, state_root);
+
+        let mut fields = ReportFields::from_state(&state).unwrap();
+        fields.pending_consolidations = Default::default();
+        let root = fields
+            .roots()
+            .unwrap()
+            .multiproof_root(roots.len(), &helpers)
+            .unwrap();
+        assert_ne!(root, state_root);
+    }
+}
@@ -0,0 +1,200 @@
+//! Per-network parameters required to derive an oracle report
+//!
+//! The config decides which validators count as Lido's so it is built into the coprocessor
+//! machine rather than read from the inputs. Each network has its own machine and so its own
+//! machine hash. The orchestrator must build inputs with the same config.
+use std::{fmt::Display, str::FromStr};
+
+use alloy_primitives::{address, Address};
+use anyhow::{anyhow, Result};
+use ethereum_consensus::{primitives::Epoch, Fork};
+use serde::{Deserialize, Serialize};
+
+use crate::credentials::{matches_any, CredentialMatcher};
+
+/// Networks with a known Lido deployment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Holesky,
+    Sepolia,
+    Hoodi,
+    /// A network configured from a file rather than built-in parameters
+    Custom,
+}
+
+impl Network {
+    /// The built-in config for this network. Returns None for [Network::Custom]
+    pub fn config(&self) -> Option<NetworkConfig> {
+        match self {
+            Network::Mainnet => Some(NetworkConfig::mainnet()),
+            Network::Holesky => Some(NetworkConfig::holesky()),
+            Network::Sepolia => Some(NetworkConfig::sepolia()),
+            Network::Hoodi => Some(NetworkConfig::hoodi()),
+            Network::Custom => None,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "holesky" => Ok(Network::Holesky),
+            "sepolia" => Ok(Network::Sepolia),
+            "hoodi" => Ok(Network::Hoodi),
+            "custom" => Ok(Network::Custom),
+            _ => Err(anyhow!("unknown network: {}", s)),
+        }
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Holesky => "holesky",
+            Network::Sepolia => "sepolia",
+            Network::Hoodi => "hoodi",
+            Network::Custom => "custom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Epochs at which each fork activates on a network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkSchedule {
+    pub altair: Epoch,
+    pub bellatrix: Epoch,
+    pub capella: Epoch,
+    pub deneb: Epoch,
+    pub electra: Epoch,
+}
+
+impl ForkSchedule {
+    /// The fork that is active at the given epoch
+    pub fn fork_at_epoch(&self, epoch: Epoch) -> Fork {
+        if epoch >= self.electra {
+            Fork::Electra
+        } else if epoch >= self.deneb {
+            Fork::Deneb
+        } else if epoch >= self.capella {
+            Fork::Capella
+        } else if epoch >= self.bellatrix {
+            Fork::Bellatrix
+        } else if epoch >= self.altair {
+            Fork::Altair
+        } else {
+            Fork::Phase0
+        }
+    }
+}
+
+/// Parameters of a network that affect how a report is derived
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub name: String,
+    /// Matchers for the withdrawal credentials used by Lido validators
+    pub withdrawal_credentials: Vec<CredentialMatcher>,
+    /// Address of the Lido withdrawal vault on the execution layer
+    pub withdrawal_vault: Address,
+    /// Unix timestamp of the beacon chain genesis
+    pub genesis_time: u64,
+    pub slots_per_epoch: u64,
+    pub fork_schedule: ForkSchedule,
+}
+
+impl NetworkConfig {
+    pub fn mainnet() -> Self {
+        Self {
+            name: Network::Mainnet.to_string(),
+            withdrawal_credentials: vec![CredentialMatcher::Address(address!(
+                "b9d7934878b5fb9610b3fe8a5e441e8fad7e293f"
+            ))],
+            withdrawal_vault: address!("b9d7934878b5fb9610b3fe8a5e441e8fad7e293f"),
+            genesis_time: 1606824023,
+            slots_per_epoch: 32,
+            fork_schedule: ForkSchedule {
+                altair: 74240,
+                bellatrix: 144896,
+                capella: 194048,
+                deneb: 269568,
+                electra: 364032,
+            },
+        }
+    }
+
+    pub fn holesky() -> Self {
+        Self {
+            name: Network::Holesky.to_string(),
+            withdrawal_credentials: vec![CredentialMatcher::Address(address!(
+                "f0179dec45a37423ead4fad5fcb136197872ead9"
+            ))],
+            withdrawal_vault: address!("f0179dec45a37423ead4fad5fcb136197872ead9"),
+            genesis_time: 1695902400,
+            slots_per_epoch: 32,
+            fork_schedule: ForkSchedule {
+                altair: 0,
+                bellatrix: 0,
+                capella: 256,
+                deneb: 29696,
+                electra: 115968,
+            },
+        }
+    }
+
+    pub fn sepolia() -> Self {
+        Self {
+            name: Network::Sepolia.to_string(),
+            withdrawal_credentials: vec![CredentialMatcher::Address(address!(
+                "de7318afa67ead6d6bbc8224dfce5ed6e4b86d76"
+            ))],
+            withdrawal_vault: address!("de7318afa67ead6d6bbc8224dfce5ed6e4b86d76"),
+            genesis_time: 1655733600,
+            slots_per_epoch: 32,
+            fork_schedule: ForkSchedule {
+                altair: 50,
+                bellatrix: 100,
+                capella: 56832,
+                deneb: 132608,
+                electra: 222464,
+            },
+        }
+    }
+
+    pub fn hoodi() -> Self {
+        Self {
+            name: Network::Hoodi.to_string(),
+            withdrawal_credentials: vec![CredentialMatcher::Address(address!(
+                "4473dcddbf77679a643bdb654dbd86d67f8d32f2"
+            ))],
+            withdrawal_vault: address!("4473dcddbf77679a643bdb654dbd86d67f8d32f2"),
+            genesis_time: 1742213400,
+            slots_per_epoch: 32,
+            fork_schedule: ForkSchedule {
+                altair: 0,
+                bellatrix: 0,
+                capella: 0,
+                deneb: 0,
+                electra: 2048,
+            },
+        }
+    }
+
+    /// Returns true if the withdrawal credentials belong to a Lido validator
+    pub fn is_lido_credential(&self, credentials: &[u8]) -> bool {
+        matches_any(&self.withdrawal_credentials, credentials)
+    }
+
+    pub fn epoch_at_slot(&self, slot: u64) -> Epoch {
+        slot / self.slots_per_epoch
+    }
+
+    /// The fork that is active at the given slot
+    pub fn fork_at_slot(&self, slot: u64) -> Fork {
+        self.fork_schedule.fork_at_epoch(self.epoch_at_slot(slot))
+    }
+}
@@ -0,0 +1,268 @@
+//! A compact beacon state format for loading into the coprocessor
+//!
+//! Only the fields needed to derive a report are kept. Every other field is replaced
+//! by its hash tree root which is enough to recompute the state root.
+use anyhow::{anyhow, ensure, Result};
+use ethereum_consensus::{
+    electra::{PendingConsolidation, PendingDeposit, PendingPartialWithdrawal},
+    phase0::Validator,
+    primitives::{Bytes32, Gwei, Slot},
+    types::mainnet::BeaconState,
+};
+use ssz_rs::prelude::*;
+
+use crate::merkle::merkleize;
+
+// mainnet preset list limits
+pub const VALIDATOR_REGISTRY_LIMIT: usize = 1 << 40;
+pub const PENDING_DEPOSITS_LIMIT: usize = 1 << 27;
+pub const PENDING_PARTIAL_WITHDRAWALS_LIMIT: usize = 1 << 27;
+pub const PENDING_CONSOLIDATIONS_LIMIT: usize = 1 << 18;
+
+/// Upper bound on the number of fields in a BeaconState of any fork
+pub const MAX_BEACON_STATE_FIELDS: usize = 64;
+
+// Indices of the kept fields in the BeaconState container. These are stable across forks
+pub const SLOT_FIELD: usize = 2;
+pub const VALIDATORS_FIELD: usize = 11;
+pub const BALANCES_FIELD: usize = 12;
+pub const PENDING_DEPOSITS_FIELD: usize = 34;
+pub const PENDING_PARTIAL_WITHDRAWALS_FIELD: usize = 35;
+pub const PENDING_CONSOLIDATIONS_FIELD: usize = 36;
+
+/// Number of BeaconState fields in the Electra fork. Only Electra has the pending queues
+pub const ELECTRA_FIELD_COUNT: usize = 37;
+
+/// Number of fields in the BeaconState container for each supported fork
+const FIELD_COUNTS: [usize; 5] = [
+    21, // phase0
+    24, // altair
+    25, // bellatrix
+    28, // capella, deneb
+    ELECTRA_FIELD_COUNT,
+];
+
+/// Check the number of fields matches a BeaconState of a supported fork
+pub fn ensure_field_count(field_count: usize) -> Result<()> {
+    ensure!(
+        FIELD_COUNTS.contains(&field_count),
+        "unexpected number of beacon state fields: {}",
+        field_count
+    );
+    Ok(())
+}
+
+/// The BeaconState fields required to derive a report.
+/// The pending queues come before the registry so the report can be derived in a single pass
+#[derive(Debug, Default, Clone, PartialEq, Eq, SimpleSerialize)]
+pub struct ReportFields {
+    pub slot: Slot,
+    pub pending_deposits: List<PendingDeposit, PENDING_DEPOSITS_LIMIT>,
+    pub pending_partial_withdrawals:
+        List<PendingPartialWithdrawal, PENDING_PARTIAL_WITHDRAWALS_LIMIT>,
+    pub pending_consolidations: List<PendingConsolidation, PENDING_CONSOLIDATIONS_LIMIT>,
+    pub validators: List<Validator, VALIDATOR_REGISTRY_LIMIT>,
+    pub balances: List<Gwei, VALIDATOR_REGISTRY_LIMIT>,
+}
+
+impl ReportFields {
+    /// Copy the report fields out of a full state
+    pub fn from_state(state: &BeaconState) -> Result<Self> {
+        let mut fields = Self {
+            slot: state.slot(),
+            validators: List::try_from(state.validators().to_vec())
+                .map_err(|(_, e)| anyhow!("{}", e))?,
+            balances: List::try_from(state.balances().to_vec())
+                .map_err(|(_, e)| anyhow!("{}", e))?,
+            ..Default::default()
+        };
+        if let BeaconState::Electra(state) = state {
+            fields.pending_deposits = List::try_from(state.pending_deposits.to_vec())
+                .map_err(|(_, e)| anyhow!("{}", e))?;
+            fields.pending_partial_withdrawals =
+                List::try_from(state.pending_partial_withdrawals.to_vec())
+                    .map_err(|(_, e)| anyhow!("{}", e))?;
+            fields.pending_consolidations = List::try_from(state.pending_consolidations.to_vec())
+                .map_err(|(_, e)| anyhow!("{}", e))?;
+        }
+        Ok(fields)
+    }
+
+    /// Indices of the report fields in a state with `field_count` fields
+    pub fn field_indices(field_count: usize) -> Vec<usize> {
+        let mut indices = vec![SLOT_FIELD, VALIDATORS_FIELD, BALANCES_FIELD];
+        if field_count == ELECTRA_FIELD_COUNT {
+            indices.extend([
+                PENDING_DEPOSITS_FIELD,
+                PENDING_PARTIAL_WITHDRAWALS_FIELD,
+                PENDING_CONSOLIDATIONS_FIELD,
+            ]);
+        }
+        indices
+    }
+
+    /// Hash tree roots of the report fields
+    pub fn roots(&self) -> Result<ReportFieldRoots> {
+        Ok(ReportFieldRoots {
+            slot: self.slot.hash_tree_root()?.0,
+            pending_deposits: self.pending_deposits.hash_tree_root()?.0,
+            pending_partial_withdrawals: self.pending_partial_withdrawals.hash_tree_root()?.0,
+            pending_consolidations: self.pending_consolidations.hash_tree_root()?.0,
+            validators: self.validators.hash_tree_root()?.0,
+            balances: self.balances.hash_tree_root()?.0,
+            pending_queues_empty: self.pending_deposits.is_empty()
+                && self.pending_partial_withdrawals.is_empty()
+                && self.pending_consolidations.is_empty(),
+        })
+    }
+}
+
+/// Hash tree roots of the [ReportFields]. This is all that is needed to check the fields
+/// against a state root so they can be computed without holding the fields in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportFieldRoots {
+    pub slot: [u8; 32],
+    pub pending_deposits: [u8; 32],
+    pub pending_partial_withdrawals: [u8; 32],
+    pub pending_consolidations: [u8; 32],
+    pub validators: [u8; 32],
+    pub balances: [u8; 32],
+    /// Non-empty pending queues are only valid in an Electra state
+    pub pending_queues_empty: bool,
+}
+
+impl ReportFieldRoots {
+    /// Set the roots of the report fields into the roots of all fields of a state
+    pub fn insert_into(&self, field_roots: &mut [[u8; 32]]) -> Result<()> {
+        ensure_field_count(field_roots.len())?;
+        field_roots[SLOT_FIELD] = self.slot;
+        field_roots[VALIDATORS_FIELD] = self.validators;
+        field_roots[BALANCES_FIELD] = self.balances;
+        if field_roots.len() == ELECTRA_FIELD_COUNT {
+            field_roots[PENDING_DEPOSITS_FIELD] = self.pending_deposits;
+            field_roots[PENDING_PARTIAL_WITHDRAWALS_FIELD] = self.pending_partial_withdrawals;
+            field_roots[PENDING_CONSOLIDATIONS_FIELD] = self.pending_consolidations;
+        } else {
+            ensure!(
+                self.pending_queues_empty,
+                "pending queues are only valid for Electra states"
+            );
+        }
+        Ok(())
+    }
+
+    /// Compute the state root from the report field roots and the roots of the other fields
+    pub fn state_root(&self, mut field_roots: Vec<[u8; 32]>) -> Result<[u8; 32]> {
+        self.insert_into(&mut field_roots)?;
+        Ok(merkleize(&field_roots))
+    }
+}
+
+/// Hash tree roots of every field of a beacon state
+pub fn field_roots(state: &BeaconState) -> Result<Vec<[u8; 32]>> {
+    macro_rules! roots {
+        ($state:expr; $($field:ident),+ $(,)?) => {
+            vec![$($state.$field.hash_tree_root()?.0),+]
+        };
+    }
+
+    let roots = match state {
+        BeaconState::Phase0(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_attestations, current_epoch_attestations, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+        ),
+        BeaconState::Altair(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_participation, current_epoch_participation, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+            inactivity_scores, current_sync_committee, next_sync_committee,
+        ),
+        BeaconState::Bellatrix(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_participation, current_epoch_participation, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+            inactivity_scores, current_sync_committee, next_sync_committee,
+            latest_execution_payload_header,
+        ),
+        BeaconState::Capella(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_participation, current_epoch_participation, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+            inactivity_scores, current_sync_committee, next_sync_committee,
+            latest_execution_payload_header, next_withdrawal_index,
+            next_withdrawal_validator_index, historical_summaries,
+        ),
+        BeaconState::Deneb(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_participation, current_epoch_participation, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+            inactivity_scores, current_sync_committee, next_sync_committee,
+            latest_execution_payload_header, next_withdrawal_index,
+            next_withdrawal_validator_index, historical_summaries,
+        ),
+        BeaconState::Electra(state) => roots!(state;
+            genesis_time, genesis_validators_root, slot, fork, latest_block_header,
+            block_roots, state_roots, historical_roots, eth1_data, eth1_data_votes,
+            eth1_deposit_index, validators, balances, randao_mixes, slashings,
+            previous_epoch_participation, current_epoch_participation, justification_bits,
+            previous_justified_checkpoint, current_justified_checkpoint, finalized_checkpoint,
+            inactivity_scores, current_sync_committee, next_sync_committee,
+            latest_execution_payload_header, next_withdrawal_index,
+            next_withdrawal_validator_index, historical_summaries,
+            deposit_requests_start_index, deposit_balance_to_consume, exit_balance_to_consume,
+            earliest_exit_epoch, consolidation_balance_to_consume, earliest_consolidation_epoch,
+            pending_deposits, pending_partial_withdrawals, pending_consolidations,
+        ),
+    };
+    Ok(roots)
+}
+
+/// A beacon state where every field not in [ReportFields] is replaced by its hash tree root
+#[derive(Debug, Default, Clone, PartialEq, Eq, SimpleSerialize)]
+pub struct PartialBeaconState {
+    /// Roots of every field of the original state.
+    /// The entries for the report fields are ignored and recomputed from `fields`
+    pub field_roots: List<Bytes32, MAX_BEACON_STATE_FIELDS>,
+    pub fields: ReportFields,
+}
+
+impl PartialBeaconState {
+    pub fn from_state(state: &BeaconState) -> Result<Self> {
+        let mut field_roots = field_roots(state)?;
+        // the report fields are recomputed so there is no need to ship their roots
+        for index in ReportFields::field_indices(field_roots.len()) {
+            field_roots[index] = [0u8; 32];
+        }
+        let field_roots = field_roots
+            .iter()
+            .map(|root| Bytes32::try_from(root.as_slice()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("{}", e))?;
+
+        Ok(Self {
+            field_roots: List::try_from(field_roots).map_err(|(_, e)| anyhow!("{}", e))?,
+            fields: ReportFields::from_state(state)?,
+        })
+    }
+
+    /// Recompute the root of the original state from the report fields and the other field roots
+    pub fn state_root(&self) -> Result<[u8; 32]> {
+        let roots: Vec<[u8; 32]> = self
+            .field_roots
+            .iter()
+            .map(|root| root.as_slice().try_into())
+            .collect::<Result<_, _>>()?;
+        self.fields.roots()?.state_root(roots)
+    }
+}
@@ -0,0 +1,497 @@
+//! Derivation of the oracle report from beacon state data
+//!
+//! The [ReportBuilder] is fed the state data one element at a time so the report can be
+//! derived without holding the whole state in memory at once. The pending queues must be fed
+//! before the validators so pending deposits can be matched to existing validators as the
+//! registry is read. In the SSZ encoded BeaconState the validators and balances (fields 11 and
+//! 12) come before the pending queues (fields 34 to 36), which is why
+//! [ReportFields](crate::ReportFields) puts the queues first.
+use std::collections::{BTreeMap, HashMap};
+
+use alloy_primitives::U256;
+use alloy_sol_types::SolValue;
+use anyhow::{ensure, Result};
+use ethereum_consensus::{
+    electra::{PendingConsolidation, PendingDeposit, PendingPartialWithdrawal},
+    phase0::Validator,
+    primitives::{Epoch, Gwei, ValidatorIndex},
+    types::mainnet::BeaconState,
+};
+
+use crate::{
+    BalanceBreakdown, NetworkConfig, Report, ReportFields, StakingModuleReport, StakingModules,
+};
+
+/// The LIP-23 report along with the per staking module and balance breakdowns
+#[derive(Debug, Clone)]
+pub struct OracleReport {
+    pub report: Report,
+    pub staking_modules: Vec<StakingModuleReport>,
+    pub balance_breakdown: BalanceBreakdown,
+}
+
+impl OracleReport {
+    /// Encode as the notice payload. This is decoded by the contract as `Report`.
+    /// The staking module counts are not included as the module mapping is not verified
+    pub fn abi_encode(&self) -> Vec<u8> {
+        self.report.abi_encode()
+    }
+
+    /// Encode the per staking module counts as `StakingModuleReport[]`, in module id order.
+    /// These are emitted as a report alongside the notice
+    pub fn staking_modules_abi_encode(&self) -> Vec<u8> {
+        self.staking_modules.abi_encode()
+    }
+}
+
+/// Derive the report from a full beacon state
+pub fn derive_report(
+    state: &BeaconState,
+    network: &NetworkConfig,
+    staking_modules: &StakingModules,
+) -> Result<OracleReport> {
+    let mut builder = ReportBuilder::new(state.slot(), network, staking_modules);
+
+    // Only Electra states have pending deposit, withdrawal and consolidation queues.
+    // Matching exhaustively ensures new forks have to be considered here
+    match state {
+        BeaconState::Phase0(_)
+        | BeaconState::Altair(_)
+        | BeaconState::Bellatrix(_)
+        | BeaconState::Capella(_)
+        | BeaconState::Deneb(_) => {}
+        BeaconState::Electra(state) => {
+            state
+                .pending_deposits
+                .iter()
+                .for_each(|d| builder.add_pending_deposit(d));
+            state
+                .pending_partial_withdrawals
+                .iter()
+                .for_each(|w| builder.add_pending_partial_withdrawal(w));
+            state
+                .pending_consolidations
+                .iter()
+                .for_each(|c| builder.add_pending_consolidation(c));
+        }
+    }
+
+    state
+        .validators()
+        .iter()
+        .for_each(|v| builder.add_validator(v));
+    state
+        .balances()
+        .iter()
+        .for_each(|b| builder.add_balance(*b));
+
+    builder.finish()
+}
+
+/// Derive the report from the report fields of a state
+pub fn derive_report_from_fields(
+    fields: &ReportFields,
+    network: &NetworkConfig,
+    staking_modules: &StakingModules,
+) -> Result<OracleReport> {
+    let mut builder = ReportBuilder::new(fields.slot, network, staking_modules);
+
+    fields
+        .pending_deposits
+        .iter()
+        .for_each(|d| builder.add_pending_deposit(d));
+    fields
+        .pending_partial_withdrawals
+        .iter()
+        .for_each(|w| builder.add_pending_partial_withdrawal(w));
+    fields
+        .pending_consolidations
+        .iter()
+        .for_each(|c| builder.add_pending_consolidation(c));
+    fields
+        .validators
+        .iter()
+        .for_each(|v| builder.add_validator(v));
+    fields.balances.iter().for_each(|b| builder.add_balance(*b));
+
+    builder.finish()
+}
+
+/// Pending deposits for a single pubkey
+///
+/// Deposit signatures are not verified here. The beacon chain drops a deposit for a new pubkey
+/// if its signature is invalid, in which case a later deposit for the pubkey creates the
+/// validator with its own credentials. Someone front-running a Lido deposit with an invalidly
+/// signed one could therefore have it counted under the wrong credentials until the queue is
+/// processed. The error is bounded by the pending deposits to new pubkeys, all of which appear in
+/// `pendingDepositsGwei`
+struct PendingPubkeyDeposits {
+    /// Credentials of the first deposit. These become the credentials of a new validator if its
+    /// signature is valid
+    withdrawal_credentials: Vec<u8>,
+    amount: Gwei,
+    /// If the pubkey belongs to an existing validator, whether that validator is Lido's
+    existing_validator_is_lido: Option<bool>,
+}
+
+/// Accumulates a report from beacon state data.
+///
+/// Pending queues must be added first, then all validators in registry order,
+/// then all balances in registry order.
+pub struct ReportBuilder<'a> {
+    network: &'a NetworkConfig,
+    current_epoch: Epoch,
+    module_index: HashMap<&'a [u8], u64>,
+    /// (deposited, exited) for each module
+    module_counts: BTreeMap<u64, (u64, u64)>,
+
+    /// Registry indices of the Lido validators, in ascending order
+    lido_indices: Vec<ValidatorIndex>,
+    next_validator_index: ValidatorIndex,
+    next_balance_index: ValidatorIndex,
+    next_lido: usize,
+
+    pending_deposits: HashMap<Vec<u8>, PendingPubkeyDeposits>,
+    pending_partial_withdrawals: Vec<(ValidatorIndex, Gwei)>,
+    pending_consolidations: Vec<(ValidatorIndex, ValidatorIndex)>,
+    consolidation_source_balances: HashMap<ValidatorIndex, Gwei>,
+
+    validator_balances_gwei: Gwei,
+    total_deposited: u64,
+    total_exited: u64,
+}
+
+impl<'a> ReportBuilder<'a> {
+    pub fn new(slot: u64, network: &'a NetworkConfig, staking_modules: &'a StakingModules) -> Self {
+        Self {
+            network,
+            current_epoch: network.epoch_at_slot(slot),
+            module_index: staking_modules.index(),
+            module_counts: staking_modules
+                .modules
+                .iter()
+                .map(|module| (module.id, (0, 0)))
+                .collect(),
+            lido_indices: Vec::new(),
+            next_validator_index: 0,
+            next_balance_index: 0,
+            next_lido: 0,
+            pending_deposits: HashMap::new(),
+            pending_partial_withdrawals: Vec::new(),
+            pending_consolidations: Vec::new(),
+            consolidation_source_balances: HashMap::new(),
+            validator_balances_gwei: 0,
+            total_deposited: 0,
+            total_exited: 0,
+        }
+    }
+
+    pub fn add_pending_deposit(&mut self, deposit: &PendingDeposit) {
+        self.pending_deposits
+            .entry(deposit.public_key.to_vec())
+            .or_insert_with(|| PendingPubkeyDeposits {
+                withdrawal_credentials: deposit.withdrawal_credentials.to_vec(),
+                amount: 0,
+                existing_validator_is_lido: None,
+            })
+            .amount += deposit.amount;
+    }
+
+    pub fn add_pending_partial_withdrawal(&mut self, withdrawal: &PendingPartialWithdrawal) {
+        self.pending_partial_withdrawals
+            .push((withdrawal.validator_index, withdrawal.amount));
+    }
+
+    pub fn add_pending_consolidation(&mut self, consolidation: &PendingConsolidation) {
+        self.pending_consolidations
+            .push((consolidation.source_index, consolidation.target_index));
+        self.consolidation_source_balances
+            .insert(consolidation.source_index, 0);
+    }
+
+    /// Add the next validator in the registry
+    pub fn add_validator(&mut self, validator: &Validator) {
+        let index = self.next_validator_index;
+        self.next_validator_index += 1;
+
+        let is_lido = self
+            .network
+            .is_lido_credential(validator.withdrawal_credentials.as_slice());
+
+        if let Some(pending) = self
+            .pending_deposits
+            .get_mut(validator.public_key.as_slice())
+        {
+            pending.existing_validator_is_lido = Some(is_lido);
+        }
+
+        if !is_lido {
+            return;
+        }
+
+        let did_exit = if validator.exit_epoch <= self.current_epoch {
+            1
+        } else {
+            0
+        };
+        self.lido_indices.push(index);
+        self.total_deposited += 1;
+        self.total_exited += did_exit;
+
+        if let Some(module_id) = self.module_index.get(validator.public_key.as_slice()) {
+            let (deposited, exited) = self.module_counts.entry(*module_id).or_default();
+            *deposited += 1;
+            *exited += did_exit;
+        }
+    }
+
+    /// Add the balance of the next validator in the registry
+    pub fn add_balance(&mut self, balance: Gwei) {
+        let index = self.next_balance_index;
+        self.next_balance_index += 1;
+
+        if let Some(source_balance) = self.consolidation_source_balances.get_mut(&index) {
+            *source_balance = balance;
+        }
+
+        if self.lido_indices.get(self.next_lido) == Some(&index) {
+            self.next_lido += 1;
+            self.validator_balances_gwei += balance;
+        }
+    }
+
+    fn is_lido_index(&self, index: ValidatorIndex) -> bool {
+        self.lido_indices.binary_search(&index).is_ok()
+    }
+
+    /// Derive the report. Fails if the validators and balances added differ in number
+    pub fn finish(self) -> Result<OracleReport> {
+        ensure!(
+            self.next_validator_index == self.next_balance_index,
+            "state has {} validators but {} balances",
+            self.next_validator_index,
+            self.next_balance_index
+        );
+
+        // deposits for new pubkeys are attributed by the credentials of the first deposit without
+        // checking its signature, see PendingPubkeyDeposits
+        let pending_deposits_gwei: Gwei = self
+            .pending_deposits
+            .values()
+            .filter(|p| {
+                p.existing_validator_is_lido
+                    .unwrap_or_else(|| self.network.is_lido_credential(&p.withdrawal_credentials))
+            })
+            .map(|p| p.amount)
+            .sum();
+
+        let pending_partial_withdrawals_gwei: Gwei = self
+            .pending_partial_withdrawals
+            .iter()
+            .filter(|(index, _)| self.is_lido_index(*index))
+            .map(|(_, amount)| amount)
+            .sum();
+
+        let (mut consolidations_out_gwei, mut consolidations_in_gwei) = (0, 0);
+        for (source, target) in self.pending_consolidations.iter() {
+            let balance = self.consolidation_source_balances[source];
+            match (self.is_lido_index(*source), self.is_lido_index(*target)) {
+                (true, false) => consolidations_out_gwei += balance,
+                (false, true) => consolidations_in_gwei += balance,
+                _ => {}
+            }
+        }
+
+        Ok(OracleReport {
+            report: Report {
+                clBalanceGwei: U256::from(self.validator_balances_gwei + pending_deposits_gwei),
+                // this comes from execution state and is set from a verified account proof
+                withdrawalVaultBalanceWei: U256::ZERO,
+                totalDepositedValidators: U256::from(self.total_deposited),
+                totalExitedValidators: U256::from(self.total_exited),
+            },
+            staking_modules: self
+                .module_counts
+                .into_iter()
+                .map(|(module_id, (deposited, exited))| StakingModuleReport {
+                    moduleId: U256::from(module_id),
+                    totalDepositedValidators: U256::from(deposited),
+                    totalExitedValidators: U256::from(exited),
+                })
+                .collect(),
+            balance_breakdown: BalanceBreakdown {
+                validatorBalancesGwei: U256::from(self.validator_balances_gwei),
+                pendingDepositsGwei: U256::from(pending_deposits_gwei),
+                pendingPartialWithdrawalsGwei: U256::from(pending_partial_withdrawals_gwei),
+                pendingConsolidationsOutGwei: U256::from(consolidations_out_gwei),
+                pendingConsolidationsInGwei: U256::from(consolidations_in_gwei),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn gwei(value: U256) -> Gwei {
+        value.to()
+    }
+
+    #[test]
+    fn derives_report_from_electra_state() {
+        let network = NetworkConfig::mainnet();
+        let report = derive_report(&electra_state(), &network, &StakingModules::default()).unwrap();
+
+        let validator_balances = balance(0) + balance(2) + balance(3);
+        let breakdown = &report.balance_breakdown;
+        assert_eq!(gwei(breakdown.validatorBalancesGwei), validator_balances);
+        assert_eq!(gwei(breakdown.pendingDepositsGwei), 5_000_000_000);
+        assert_eq!(gwei(breakdown.pendingPartialWithdrawalsGwei), 1_000_000_000);
+        assert_eq!(gwei(breakdown.pendingConsolidationsOutGwei), balance(0));
+        assert_eq!(gwei(breakdown.pendingConsolidationsInGwei), balance(4));
+
+        assert_eq!(
+            gwei(report.report.clBalanceGwei),
+            validator_balances + 5_000_000_000
+        );
+        assert_eq!(report.report.totalDepositedValidators, U256::from(3));
+        assert_eq!(report.report.totalExitedValidators, U256::from(1));
+        assert_eq!(report.report.withdrawalVaultBalanceWei, U256::ZERO);
+    }
+
+    fn module_counts(report: &OracleReport) -> Vec<(u64, u64, u64)> {
+        report
+            .staking_modules
+            .iter()
+            .map(|m| {
+                (
+                    m.moduleId.to(),
+                    m.totalDepositedValidators.to(),
+                    m.totalExitedValidators.to(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_validators_per_staking_module() {
+        let network = NetworkConfig::mainnet();
+        let report = derive_report(&electra_state(), &network, &staking_modules()).unwrap();
+
+        // validator 1 is in module 2 but isn't Lido's, and module 3 only has a pending deposit
+        assert_eq!(
+            module_counts(&report),
+            vec![(1, 2, 1), (2, 1, 0), (3, 0, 0)]
+        );
+        // the mapping doesn't change the aggregate report
+        let without =
+            derive_report(&electra_state(), &network, &StakingModules::default()).unwrap();
+        assert_eq!(report.abi_encode(), without.abi_encode());
+        assert!(without.staking_modules.is_empty());
+
+        let decoded =
+            Vec::<StakingModuleReport>::abi_decode(&report.staking_modules_abi_encode(), true)
+                .unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].totalExitedValidators, U256::from(1));
+    }
+
+    #[test]
+    fn unmapped_lido_validators_only_count_in_the_totals() {
+        let network = NetworkConfig::mainnet();
+        let modules = staking_modules();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_validator(&validator(0, lido_credentials(), FAR_FUTURE_EPOCH));
+        builder.add_validator(&validator(20, lido_credentials(), 0));
+        (0..2).for_each(|_| builder.add_balance(1));
+        let report = builder.finish().unwrap();
+
+        assert_eq!(report.report.totalDepositedValidators, U256::from(2));
+        assert_eq!(report.report.totalExitedValidators, U256::from(1));
+        assert_eq!(
+            module_counts(&report),
+            vec![(1, 1, 0), (2, 0, 0), (3, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn pending_deposits_for_new_pubkeys_use_first_credentials() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_pending_deposit(&pending_deposit(1, lido_credentials(), 1));
+        builder.add_pending_deposit(&pending_deposit(1, other_credentials(), 2));
+        builder.add_pending_deposit(&pending_deposit(2, other_credentials(), 4));
+        builder.add_pending_deposit(&pending_deposit(2, lido_credentials(), 8));
+        let report = builder.finish().unwrap();
+
+        assert_eq!(gwei(report.balance_breakdown.pendingDepositsGwei), 3);
+        assert_eq!(gwei(report.report.clBalanceGwei), 3);
+    }
+
+    #[test]
+    fn pending_deposits_to_existing_validators_follow_the_validator() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_pending_deposit(&pending_deposit(0, other_credentials(), 1));
+        builder.add_pending_deposit(&pending_deposit(1, lido_credentials(), 2));
+        builder.add_validator(&validator(0, lido_credentials(), FAR_FUTURE_EPOCH));
+        builder.add_validator(&validator(1, other_credentials(), FAR_FUTURE_EPOCH));
+        builder.add_balance(10);
+        builder.add_balance(20);
+        let report = builder.finish().unwrap();
+
+        assert_eq!(gwei(report.balance_breakdown.validatorBalancesGwei), 10);
+        assert_eq!(gwei(report.balance_breakdown.pendingDepositsGwei), 1);
+        assert_eq!(gwei(report.report.clBalanceGwei), 11);
+    }
+
+    #[test]
+    fn withdrawals_and_consolidations_do_not_change_balance() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_pending_partial_withdrawal(&pending_partial_withdrawal(0, 5));
+        builder.add_pending_partial_withdrawal(&pending_partial_withdrawal(1, 7));
+        builder.add_pending_consolidation(&pending_consolidation(0, 1));
+        builder.add_pending_consolidation(&pending_consolidation(1, 0));
+        builder.add_validator(&validator(0, lido_credentials(), FAR_FUTURE_EPOCH));
+        builder.add_validator(&validator(1, other_credentials(), FAR_FUTURE_EPOCH));
+        builder.add_balance(10);
+        builder.add_balance(20);
+        let report = builder.finish().unwrap();
+
+        let breakdown = &report.balance_breakdown;
+        assert_eq!(gwei(breakdown.pendingPartialWithdrawalsGwei), 5);
+        assert_eq!(gwei(breakdown.pendingConsolidationsOutGwei), 10);
+        assert_eq!(gwei(breakdown.pendingConsolidationsInGwei), 20);
+        assert_eq!(gwei(report.report.clBalanceGwei), 10);
+    }
+
+    #[test]
+    fn counts_exited_validators_at_the_state_epoch() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_validator(&validator(0, lido_credentials(), 100));
+        builder.add_validator(&validator(1, lido_credentials(), 101));
+        builder.add_validator(&validator(2, other_credentials(), 0));
+        (0..3).for_each(|_| builder.add_balance(1));
+        let report = builder.finish().unwrap();
+
+        assert_eq!(report.report.totalDepositedValidators, U256::from(2));
+        assert_eq!(report.report.totalExitedValidators, U256::from(1));
+    }
+
+    #[test]
+    fn rejects_mismatched_validators_and_balances() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut builder = ReportBuilder::new(STATE_SLOT, &network, &modules);
+        builder.add_validator(&validator(0, lido_credentials(), FAR_FUTURE_EPOCH));
+        assert!(builder.finish().is_err());
+    }
+}
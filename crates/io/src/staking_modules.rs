@@ -0,0 +1,121 @@
+//! Attribution of Lido validators to the staking module that deposited them
+use std::collections::{HashMap, HashSet};
+
+use alloy_primitives::Bytes;
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// A Lido staking module (e.g. curated, Simple DVT, Community Staking)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StakingModule {
+    /// Id of the module in the Lido StakingRouter
+    pub id: u64,
+    pub name: String,
+    /// BLS public keys of the validators deposited by this module
+    pub pubkeys: Vec<Bytes>,
+}
+
+/// Mapping of validator public keys to the staking module they belong to.
+/// This is supplied by whoever runs the orchestrator and committed to by the manifest, so the
+/// mapping a set of per module counts was derived from can always be recovered. It is not checked
+/// against chain data, which is why the counts are emitted alongside the report rather than
+/// stored by the contract
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StakingModules {
+    pub modules: Vec<StakingModule>,
+}
+
+impl StakingModules {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let modules: Self = serde_cbor::from_slice(bytes)?;
+        modules.validate()?;
+        Ok(modules)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_cbor::to_vec(self)?)
+    }
+
+    /// Check module ids are unique and every pubkey is a 48 byte BLS key assigned to only one
+    /// module, so each validator is counted at most once
+    pub fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        let mut pubkeys = HashSet::new();
+        for module in &self.modules {
+            ensure!(
+                ids.insert(module.id),
+                "staking module {} is listed more than once",
+                module.id
+            );
+            for pubkey in &module.pubkeys {
+                ensure!(
+                    pubkey.len() == 48,
+                    "staking module {} has a pubkey of {} bytes",
+                    module.id,
+                    pubkey.len()
+                );
+                ensure!(
+                    pubkeys.insert(pubkey.as_ref()),
+                    "pubkey {:?} is assigned to more than one staking module",
+                    pubkey
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Build a lookup from validator public key to module id
+    pub fn index(&self) -> HashMap<&[u8], u64> {
+        self.modules
+            .iter()
+            .flat_map(|module| module.pubkeys.iter().map(|pk| (pk.as_ref(), module.id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::staking_modules;
+
+    fn module(id: u64, pubkeys: &[u8]) -> StakingModule {
+        StakingModule {
+            id,
+            name: format!("module {}", id),
+            pubkeys: pubkeys
+                .iter()
+                .map(|pk| Bytes::from(vec![*pk; 48]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_cbor() {
+        let modules = staking_modules();
+        let decoded = StakingModules::from_bytes(&modules.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.index(), modules.index());
+        assert_eq!(decoded.index().get(&[3u8; 48][..]), Some(&2));
+    }
+
+    #[test]
+    fn rejects_ambiguous_mappings() {
+        let duplicate_id = StakingModules {
+            modules: vec![module(1, &[0]), module(1, &[1])],
+        };
+        assert!(duplicate_id.validate().is_err());
+
+        let duplicate_pubkey = StakingModules {
+            modules: vec![module(1, &[0]), module(2, &[1, 0])],
+        };
+        assert!(duplicate_pubkey.validate().is_err());
+        assert!(StakingModules::from_bytes(&duplicate_pubkey.to_bytes().unwrap()).is_err());
+
+        let mut short_pubkey = module(1, &[0]);
+        short_pubkey.pubkeys.push(Bytes::from(vec![1; 47]));
+        assert!(StakingModules {
+            modules: vec![short_pubkey]
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,457 @@
+//! Streaming derivation of a report from the SSZ encoded state formats
+//!
+//! The coprocessor receives the state as a series of chunks. Rather than reassembling and
+//! deserializing it, the parsers here consume the chunks as they arrive. Each list element is
+//! fed to a [ReportBuilder] and hashed into an [IncrementalMerkleizer], so memory use is bounded
+//! by the report being built rather than the size of the state.
+//!
+//! Every list in [ReportFields] has fixed size elements so the encoding can be split into
+//! elements using only the offsets at the start of the container.
+use anyhow::{anyhow, ensure, Result};
+use ethereum_consensus::{
+    electra::{PendingConsolidation, PendingDeposit, PendingPartialWithdrawal},
+    phase0::Validator,
+};
+use ssz_rs::prelude::*;
+
+use crate::{
+    merkle::{mix_in_length, IncrementalMerkleizer},
+    partial_state::{
+        ReportFieldRoots, MAX_BEACON_STATE_FIELDS, PENDING_CONSOLIDATIONS_LIMIT,
+        PENDING_DEPOSITS_LIMIT, PENDING_PARTIAL_WITHDRAWALS_LIMIT, VALIDATOR_REGISTRY_LIMIT,
+    },
+    NetworkConfig, OracleReport, ReportBuilder, StakingModules,
+};
+
+const OFFSET_SIZE: usize = 4;
+const BYTES_PER_CHUNK: usize = 32;
+
+// Encoded sizes of the list elements
+const PENDING_DEPOSIT_SIZE: usize = 192;
+const PENDING_PARTIAL_WITHDRAWAL_SIZE: usize = 24;
+const PENDING_CONSOLIDATION_SIZE: usize = 16;
+const VALIDATOR_SIZE: usize = 121;
+const BALANCE_SIZE: usize = 8;
+
+/// The lists of [ReportFields](crate::ReportFields) in the order they are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum List {
+    PendingDeposits,
+    PendingPartialWithdrawals,
+    PendingConsolidations,
+    Validators,
+    Balances,
+}
+
+const LISTS: [List; 5] = [
+    List::PendingDeposits,
+    List::PendingPartialWithdrawals,
+    List::PendingConsolidations,
+    List::Validators,
+    List::Balances,
+];
+
+/// The slot followed by an offset for each list
+const REPORT_FIELDS_FIXED_SIZE: usize = 8 + LISTS.len() * OFFSET_SIZE;
+
+impl List {
+    fn element_size(&self) -> usize {
+        match self {
+            List::PendingDeposits => PENDING_DEPOSIT_SIZE,
+            List::PendingPartialWithdrawals => PENDING_PARTIAL_WITHDRAWAL_SIZE,
+            List::PendingConsolidations => PENDING_CONSOLIDATION_SIZE,
+            List::Validators => VALIDATOR_SIZE,
+            List::Balances => BALANCE_SIZE,
+        }
+    }
+
+    /// Depth of the merkle tree of the list data, from the list limit in chunks
+    fn depth(&self) -> u32 {
+        let limit = match self {
+            List::PendingDeposits => PENDING_DEPOSITS_LIMIT,
+            List::PendingPartialWithdrawals => PENDING_PARTIAL_WITHDRAWALS_LIMIT,
+            List::PendingConsolidations => PENDING_CONSOLIDATIONS_LIMIT,
+            List::Validators => VALIDATOR_REGISTRY_LIMIT,
+            // balances are packed into chunks
+            List::Balances => VALIDATOR_REGISTRY_LIMIT * BALANCE_SIZE / BYTES_PER_CHUNK,
+        };
+        limit.trailing_zeros()
+    }
+}
+
+fn read_offset(bytes: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(bytes[at..at + OFFSET_SIZE].try_into().unwrap()) as usize
+}
+
+/// Parses SSZ encoded [ReportFields](crate::ReportFields) incrementally,
+/// deriving the report and the field roots
+pub struct ReportFieldsParser<'a> {
+    network: &'a NetworkConfig,
+    staking_modules: &'a StakingModules,
+    /// Bytes received that do not yet make up a whole element
+    buffer: Vec<u8>,
+    /// Offset in the encoding of the start of `buffer`
+    position: usize,
+    /// Set once the fixed part of the container has been read
+    header: Option<Header<'a>>,
+}
+
+struct Header<'a> {
+    slot: u64,
+    builder: ReportBuilder<'a>,
+    /// Offsets where each list ends. The last list runs to the end of the data
+    ends: [usize; LISTS.len() - 1],
+    /// Index in [LISTS] of the list being read
+    list: usize,
+    merkleizers: [IncrementalMerkleizer; LISTS.len()],
+    balance_count: u64,
+    /// Balances not yet making up a whole chunk
+    balance_chunk: [u8; BYTES_PER_CHUNK],
+}
+
+impl<'a> ReportFieldsParser<'a> {
+    pub fn new(network: &'a NetworkConfig, staking_modules: &'a StakingModules) -> Self {
+        Self {
+            network,
+            staking_modules,
+            buffer: Vec::new(),
+            position: 0,
+            header: None,
+        }
+    }
+
+    /// Consume the next bytes of the encoding
+    pub fn feed(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(data);
+        let buffer = std::mem::take(&mut self.buffer);
+
+        let mut consumed = 0;
+        if self.header.is_none() {
+            if buffer.len() < REPORT_FIELDS_FIXED_SIZE {
+                self.buffer = buffer;
+                return Ok(());
+            }
+            self.header = Some(self.read_header(&buffer[..REPORT_FIELDS_FIXED_SIZE])?);
+            consumed = REPORT_FIELDS_FIXED_SIZE;
+        }
+
+        let header = self.header.as_mut().unwrap();
+        loop {
+            header.advance(self.position + consumed);
+            let size = LISTS[header.list].element_size();
+            if buffer.len() - consumed < size {
+                break;
+            }
+            header.add_element(&buffer[consumed..consumed + size])?;
+            consumed += size;
+        }
+
+        self.buffer = buffer[consumed..].to_vec();
+        self.position += consumed;
+        Ok(())
+    }
+
+    fn read_header(&self, bytes: &[u8]) -> Result<Header<'a>> {
+        let slot = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let offsets: Vec<usize> = (0..LISTS.len())
+            .map(|i| read_offset(bytes, 8 + i * OFFSET_SIZE))
+            .collect();
+        ensure!(
+            offsets[0] == REPORT_FIELDS_FIXED_SIZE,
+            "invalid first offset {} in report fields",
+            offsets[0]
+        );
+        for (i, list) in LISTS[..LISTS.len() - 1].iter().enumerate() {
+            ensure!(
+                offsets[i + 1] >= offsets[i]
+                    && (offsets[i + 1] - offsets[i]) % list.element_size() == 0,
+                "invalid offset for the list after {:?} in report fields",
+                list
+            );
+        }
+
+        Ok(Header {
+            slot,
+            builder: ReportBuilder::new(slot, self.network, self.staking_modules),
+            ends: offsets[1..].try_into().unwrap(),
+            list: 0,
+            merkleizers: LISTS.map(|list| IncrementalMerkleizer::new(list.depth())),
+            balance_count: 0,
+            balance_chunk: [0u8; BYTES_PER_CHUNK],
+        })
+    }
+
+    /// Finish parsing, returning the roots of the report fields and the derived report
+    pub fn finish(mut self) -> Result<(ReportFieldRoots, OracleReport)> {
+        let mut header = self
+            .header
+            .take()
+            .ok_or_else(|| anyhow!("report fields ended before the end of the fixed part"))?;
+        ensure!(
+            self.buffer.is_empty(),
+            "report fields end with a partial {:?} element",
+            LISTS[header.list]
+        );
+        header.advance(self.position);
+        ensure!(
+            header.list == LISTS.len() - 1,
+            "report fields ended in the {:?} list",
+            LISTS[header.list]
+        );
+        header.finish()
+    }
+}
+
+impl<'a> Header<'a> {
+    /// Move on to the next non-empty list if the current list ends at `position`
+    fn advance(&mut self, position: usize) {
+        while self.list < self.ends.len() && position == self.ends[self.list] {
+            self.list += 1;
+        }
+    }
+
+    fn add_element(&mut self, bytes: &[u8]) -> Result<()> {
+        let list = LISTS[self.list];
+        let root = match list {
+            List::PendingDeposits => {
+                let deposit = PendingDeposit::deserialize(bytes)?;
+                self.builder.add_pending_deposit(&deposit);
+                deposit.hash_tree_root()?.0
+            }
+            List::PendingPartialWithdrawals => {
+                let withdrawal = PendingPartialWithdrawal::deserialize(bytes)?;
+                self.builder.add_pending_partial_withdrawal(&withdrawal);
+                withdrawal.hash_tree_root()?.0
+            }
+            List::PendingConsolidations => {
+                let consolidation = PendingConsolidation::deserialize(bytes)?;
+                self.builder.add_pending_consolidation(&consolidation);
+                consolidation.hash_tree_root()?.0
+            }
+            List::Validators => {
+                let validator = Validator::deserialize(bytes)?;
+                self.builder.add_validator(&validator);
+                validator.hash_tree_root()?.0
+            }
+            List::Balances => {
+                self.builder
+                    .add_balance(u64::from_le_bytes(bytes.try_into().unwrap()));
+                let at = (self.balance_count as usize * BALANCE_SIZE) % BYTES_PER_CHUNK;
+                self.balance_chunk[at..at + BALANCE_SIZE].copy_from_slice(bytes);
+                self.balance_count += 1;
+                if at + BALANCE_SIZE < BYTES_PER_CHUNK {
+                    return Ok(());
+                }
+                std::mem::take(&mut self.balance_chunk)
+            }
+        };
+        ensure!(
+            self.merkleizers[self.list].count() < 1 << list.depth(),
+            "{:?} list exceeds its limit",
+            list
+        );
+        self.merkleizers[self.list].push(root);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(ReportFieldRoots, OracleReport)> {
+        let balances = LISTS.len() - 1;
+        if self.balance_count as usize * BALANCE_SIZE % BYTES_PER_CHUNK != 0 {
+            let chunk = std::mem::take(&mut self.balance_chunk);
+            self.merkleizers[balances].push(chunk);
+        }
+
+        let validator_count = self.merkleizers[List::Validators as usize].count();
+        ensure!(
+            validator_count == self.balance_count,
+            "state has {} validators but {} balances",
+            validator_count,
+            self.balance_count
+        );
+
+        let list_root = |list: List| {
+            let merkleizer = &self.merkleizers[list as usize];
+            let length = match list {
+                List::Balances => self.balance_count,
+                _ => merkleizer.count(),
+            };
+            mix_in_length(&merkleizer.root(), length)
+        };
+        let mut slot = [0u8; 32];
+        slot[..8].copy_from_slice(&self.slot.to_le_bytes());
+
+        let roots = ReportFieldRoots {
+            slot,
+            pending_deposits: list_root(List::PendingDeposits),
+            pending_partial_withdrawals: list_root(List::PendingPartialWithdrawals),
+            pending_consolidations: list_root(List::PendingConsolidations),
+            validators: list_root(List::Validators),
+            balances: list_root(List::Balances),
+            pending_queues_empty: LISTS[..List::Validators as usize]
+                .iter()
+                .all(|list| self.merkleizers[*list as usize].count() == 0),
+        };
+        Ok((roots, self.builder.finish()?))
+    }
+}
+
+/// Parses an SSZ encoded [PartialBeaconState](crate::PartialBeaconState) incrementally,
+/// deriving the report and the state root
+pub struct PartialStateParser<'a> {
+    /// Bytes of the field roots list until it has been read
+    prefix: Vec<u8>,
+    field_roots: Option<Vec<[u8; 32]>>,
+    fields: ReportFieldsParser<'a>,
+}
+
+/// Offsets of the two variable size fields of the container
+const PARTIAL_STATE_FIXED_SIZE: usize = 2 * OFFSET_SIZE;
+
+impl<'a> PartialStateParser<'a> {
+    pub fn new(network: &'a NetworkConfig, staking_modules: &'a StakingModules) -> Self {
+        Self {
+            prefix: Vec::new(),
+            field_roots: None,
+            fields: ReportFieldsParser::new(network, staking_modules),
+        }
+    }
+
+    /// Consume the next bytes of the encoding
+    pub fn feed(&mut self, data: &[u8]) -> Result<()> {
+        if self.field_roots.is_some() {
+            return self.fields.feed(data);
+        }
+
+        self.prefix.extend_from_slice(data);
+        if self.prefix.len() < PARTIAL_STATE_FIXED_SIZE {
+            return Ok(());
+        }
+        let roots_offset = read_offset(&self.prefix, 0);
+        let fields_offset = read_offset(&self.prefix, OFFSET_SIZE);
+        ensure!(
+            roots_offset == PARTIAL_STATE_FIXED_SIZE
+                && fields_offset >= roots_offset
+                && (fields_offset - roots_offset) % BYTES_PER_CHUNK == 0
+                && (fields_offset - roots_offset) / BYTES_PER_CHUNK <= MAX_BEACON_STATE_FIELDS,
+            "invalid offsets in partial beacon state"
+        );
+        if self.prefix.len() < fields_offset {
+            return Ok(());
+        }
+
+        let rest = self.prefix.split_off(fields_offset);
+        self.field_roots = Some(
+            self.prefix[roots_offset..]
+                .chunks(BYTES_PER_CHUNK)
+                .map(|root| root.try_into().unwrap())
+                .collect(),
+        );
+        self.prefix = Vec::new();
+        self.fields.feed(&rest)
+    }
+
+    /// Finish parsing, returning the state root and the derived report
+    pub fn finish(self) -> Result<([u8; 32], OracleReport)> {
+        let field_roots = self
+            .field_roots
+            .ok_or_else(|| anyhow!("partial beacon state ended before the report fields"))?;
+        let (roots, report) = self.fields.finish()?;
+        Ok((roots.state_root(field_roots)?, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_sol_types::SolValue;
+
+    use super::*;
+    use crate::{
+        build_multiproof, derive_report, field_roots, test_utils::*, PartialBeaconState,
+        ReportFields,
+    };
+
+    /// Chunk sizes that split elements, offsets and roots at awkward places
+    const CHUNK_SIZES: [usize; 5] = [1, 7, 33, 121, 1000];
+
+    fn encoded(report: &OracleReport) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        (
+            report.report.abi_encode(),
+            report.balance_breakdown.abi_encode(),
+            report.staking_modules_abi_encode(),
+        )
+    }
+
+    #[test]
+    fn partial_state_in_chunks_matches_state() {
+        let network = NetworkConfig::mainnet();
+        let modules = staking_modules();
+        let state = electra_state();
+        let expected = derive_report(&state, &network, &modules).unwrap();
+        let mut bytes = Vec::new();
+        PartialBeaconState::from_state(&state)
+            .unwrap()
+            .serialize(&mut bytes)
+            .unwrap();
+
+        for size in CHUNK_SIZES {
+            let mut parser = PartialStateParser::new(&network, &modules);
+            for chunk in bytes.chunks(size) {
+                parser.feed(chunk).unwrap();
+            }
+            let (state_root, report) = parser.finish().unwrap();
+            assert_eq!(
+                state_root,
+                state.hash_tree_root().unwrap().0,
+                "chunk size {}",
+                size
+            );
+            assert_eq!(encoded(&report), encoded(&expected), "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn report_fields_in_chunks_match_state() {
+        let network = NetworkConfig::mainnet();
+        let modules = staking_modules();
+        let state = electra_state();
+        let expected = derive_report(&state, &network, &modules).unwrap();
+        let roots = field_roots(&state).unwrap();
+        let helpers = build_multiproof(&roots);
+        let mut bytes = Vec::new();
+        ReportFields::from_state(&state)
+            .unwrap()
+            .serialize(&mut bytes)
+            .unwrap();
+
+        for size in CHUNK_SIZES {
+            let mut parser = ReportFieldsParser::new(&network, &modules);
+            for chunk in bytes.chunks(size) {
+                parser.feed(chunk).unwrap();
+            }
+            let (field_roots, report) = parser.finish().unwrap();
+            let state_root = field_roots.multiproof_root(roots.len(), &helpers).unwrap();
+            assert_eq!(
+                state_root,
+                state.hash_tree_root().unwrap().0,
+                "chunk size {}",
+                size
+            );
+            assert_eq!(encoded(&report), encoded(&expected), "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn truncated_report_fields_are_rejected() {
+        let network = NetworkConfig::mainnet();
+        let modules = StakingModules::default();
+        let mut bytes = Vec::new();
+        ReportFields::from_state(&electra_state())
+            .unwrap()
+            .serialize(&mut bytes)
+            .unwrap();
+
+        let mut parser = ReportFieldsParser::new(&network, &modules);
+        parser.feed(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(parser.finish().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Beacon state data for tests
+//!
+//! Validators and pending deposits are decoded from their SSZ encoding so any bytes can be used
+//! as a public key.
+use alloy_primitives::{Address, Bytes};
+use ethereum_consensus::{
+    electra::{self, PendingConsolidation, PendingDeposit, PendingPartialWithdrawal},
+    phase0::Validator,
+    primitives::{Epoch, Gwei, Slot},
+    types::mainnet::BeaconState,
+};
+use ssz_rs::prelude::*;
+
+use crate::{NetworkConfig, StakingModule, StakingModules};
+
+pub const FAR_FUTURE_EPOCH: Epoch = u64::MAX;
+
+/// Slot of [electra_state], in epoch 100
+pub const STATE_SLOT: Slot = 3200;
+
+/// Execution layer withdrawal credentials for an address
+pub fn credentials(prefix: u8, address: Address) -> [u8; 32] {
+    let mut credentials = [0u8; 32];
+    credentials[0] = prefix;
+    credentials[12..].copy_from_slice(address.as_slice());
+    credentials
+}
+
+/// 0x01 credentials of the mainnet Lido withdrawal vault
+pub fn lido_credentials() -> [u8; 32] {
+    credentials(0x01, NetworkConfig::mainnet().withdrawal_vault)
+}
+
+/// 0x01 credentials that don't belong to Lido
+pub fn other_credentials() -> [u8; 32] {
+    credentials(0x01, Address::repeat_byte(0x11))
+}
+
+pub fn validator(pubkey: u8, credentials: [u8; 32], exit_epoch: Epoch) -> Validator {
+    let mut bytes = Vec::new();
+    bytes.extend([pubkey; 48]);
+    bytes.extend(credentials);
+    bytes.extend(32_000_000_000u64.to_le_bytes()); // effective_balance
+    bytes.push(0); // slashed
+    bytes.extend(0u64.to_le_bytes()); // activation_eligibility_epoch
+    bytes.extend(0u64.to_le_bytes()); // activation_epoch
+    bytes.extend(exit_epoch.to_le_bytes());
+    bytes.extend(exit_epoch.to_le_bytes()); // withdrawable_epoch
+    Validator::deserialize(&bytes).unwrap()
+}
+
+pub fn pending_deposit(pubkey: u8, credentials: [u8; 32], amount: Gwei) -> PendingDeposit {
+    let mut bytes = Vec::new();
+    bytes.extend([pubkey; 48]);
+    bytes.extend(credentials);
+    bytes.extend(amount.to_le_bytes());
+    bytes.extend([0u8; 96]); // signature
+    bytes.extend(0u64.to_le_bytes()); // slot
+    PendingDeposit::deserialize(&bytes).unwrap()
+}
+
+pub fn pending_partial_withdrawal(
+    validator_index: usize,
+    amount: Gwei,
+) -> PendingPartialWithdrawal {
+    PendingPartialWithdrawal {
+        validator_index,
+        amount,
+        withdrawable_epoch: 0,
+    }
+}
+
+pub fn pending_consolidation(source_index: usize, target_index: usize) -> PendingConsolidation {
+    PendingConsolidation {
+        source_index,
+        target_index,
+    }
+}
+
+/// Balance of each validator in [electra_state]
+pub fn balance(index: usize) -> Gwei {
+    32_000_000_000 + index as Gwei * 1_000
+}
+
+/// An Electra state exercising each of the report rules:
+///
+/// | index | credentials  | status         |
+/// |-------|--------------|----------------|
+/// | 0     | Lido 0x01    | active         |
+/// | 1     | other        | active         |
+/// | 2     | Lido 0x01    | exited         |
+/// | 3     | Lido 0x02    | active         |
+/// | 4     | other        | active         |
+///
+/// Pending deposits are made to new and existing pubkeys with both kinds of credentials, and
+/// consolidations move balance into, out of and within Lido
+pub fn electra_state() -> BeaconState {
+    let validators = vec![
+        validator(0, lido_credentials(), FAR_FUTURE_EPOCH),
+        validator(1, other_credentials(), FAR_FUTURE_EPOCH),
+        validator(2, lido_credentials(), 50),
+        validator(
+            3,
+            credentials(0x02, NetworkConfig::mainnet().withdrawal_vault),
+            FAR_FUTURE_EPOCH,
+        ),
+        validator(4, other_credentials(), FAR_FUTURE_EPOCH),
+    ];
+    let balances = (0..validators.len()).map(balance).collect::<Vec<_>>();
+
+    let mut state = electra::mainnet::BeaconState {
+        slot: STATE_SLOT,
+        validators: List::try_from(validators).unwrap(),
+        balances: List::try_from(balances).unwrap(),
+        ..Default::default()
+    };
+    state.pending_deposits = List::try_from(vec![
+        // new pubkey with Lido credentials: counted
+        pending_deposit(10, lido_credentials(), 1_000_000_000),
+        // new pubkey with other credentials: not counted, nor is a later Lido deposit for it
+        pending_deposit(11, other_credentials(), 2_000_000_000),
+        pending_deposit(11, lido_credentials(), 5_000_000_000),
+        // top up of a non-Lido validator with Lido credentials: not counted
+        pending_deposit(1, lido_credentials(), 3_000_000_000),
+        // top up of a Lido validator with other credentials: counted
+        pending_deposit(0, other_credentials(), 4_000_000_000),
+    ])
+    .unwrap();
+    state.pending_partial_withdrawals = List::try_from(vec![
+        pending_partial_withdrawal(0, 1_000_000_000),
+        pending_partial_withdrawal(1, 7),
+    ])
+    .unwrap();
+    state.pending_consolidations = List::try_from(vec![
+        // Lido to other
+        pending_consolidation(0, 1),
+        // other to Lido
+        pending_consolidation(4, 3),
+        // within Lido
+        pending_consolidation(2, 3),
+    ])
+    .unwrap();
+    BeaconState::Electra(state)
+}
+
+/// Staking modules for the validators of [electra_state]. Module 1 holds a live and an exited Lido
+/// validator, module 2 a Lido validator and a validator without Lido credentials, and module 3
+/// only a pubkey with a pending deposit
+pub fn staking_modules() -> StakingModules {
+    let module = |id: u64, pubkeys: &[u8]| StakingModule {
+        id,
+        name: format!("module {}", id),
+        pubkeys: pubkeys
+            .iter()
+            .map(|pk| Bytes::from(vec![*pk; 48]))
+            .collect(),
+    };
+    StakingModules {
+        modules: vec![module(1, &[0, 2]), module(2, &[3, 1]), module(3, &[10])],
+    }
+}
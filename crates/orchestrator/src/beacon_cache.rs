@@ -0,0 +1,219 @@
+//! On-disk cache of beacon blocks and states
+//!
+//! Entries are keyed by block root or state root so they never go stale, unlike data requested
+//! by a named id such as `head`. Each entry holds the fork name on its first line followed by the
+//! SSZ encoded object. When the cache grows past its maximum size the least recently used entries
+//! are evicted.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use ethereum_consensus::{primitives::Root, Fork};
+
+use crate::ssz_files;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CacheArgs {
+    /// Directory to cache blocks and states fetched from the beacon node in
+    #[clap(long, env, default_value = "./beacon-cache")]
+    pub cache_dir: PathBuf,
+
+    /// Size in MiB the cache may grow to before the least recently used entries are evicted
+    #[clap(long, env, default_value_t = 4096)]
+    pub cache_max_mib: u64,
+}
+
+impl CacheArgs {
+    pub fn cache(&self) -> BeaconCache {
+        BeaconCache::new(self.cache_dir.clone(), self.cache_max_mib * 1024 * 1024)
+    }
+}
+
+/// The kinds of object stored in the cache, each in its own directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// Signed blocks by block root
+    Block,
+    /// States by state root
+    State,
+}
+
+impl CacheKind {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            CacheKind::Block => "blocks",
+            CacheKind::State => "states",
+        }
+    }
+}
+
+/// Entries removed by a prune
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PruneStats {
+    pub removed: usize,
+    pub freed: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BeaconCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl BeaconCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    fn path(&self, kind: CacheKind, root: &Root) -> PathBuf {
+        self.dir.join(kind.dir_name()).join(root_hex(root))
+    }
+
+    /// Look up an entry, returning its fork and SSZ encoded data.
+    /// A hit marks the entry as recently used
+    pub fn get(&self, kind: CacheKind, root: &Root) -> io::Result<Option<(Fork, Vec<u8>)>> {
+        let path = self.path(kind, root);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let Some(fork) = parse_entry_fork(&data) else {
+            tracing::warn!("Removing corrupt cache entry {:?}", path);
+            std::fs::remove_file(&path)?;
+            return Ok(None);
+        };
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())?;
+        let start = data.iter().position(|b| *b == b'\n').unwrap_or_default() + 1;
+        Ok(Some((fork, data[start..].to_vec())))
+    }
+
+    /// Remove an entry, such as one that failed to decode or did not match its root
+    pub fn remove(&self, kind: CacheKind, root: &Root) -> io::Result<()> {
+        remove_entry(&self.path(kind, root))
+    }
+
+    /// Store an entry then evict old entries if the cache has grown too large.
+    /// The entry is written to a temporary file first so readers never see it half written
+    pub fn insert(&self, kind: CacheKind, root: &Root, fork: Fork, data: &[u8]) -> io::Result<()> {
+        let path = self.path(kind, root);
+        std::fs::create_dir_all(self.dir.join(kind.dir_name()))?;
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        writeln!(file, "{}", fork)?;
+        file.write_all(data)?;
+        drop(file);
+        std::fs::rename(&tmp, &path)?;
+
+        let stats = self.prune(self.max_size)?;
+        if stats.removed > 0 {
+            tracing::debug!(
+                "Evicted {} cache entries freeing {} bytes",
+                stats.removed,
+                stats.freed
+            );
+        }
+        Ok(())
+    }
+
+    /// Remove the least recently used entries until the cache is no larger than `max_size`
+    pub fn prune(&self, max_size: u64) -> io::Result<PruneStats> {
+        let mut entries = Vec::new();
+        for kind in [CacheKind::Block, CacheKind::State] {
+            let dir = self.dir.join(kind.dir_name());
+            if !dir.exists() {
+                continue;
+            }
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                // entries still being written by an insert, possibly in another process
+                if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+                    continue;
+                }
+                let metadata = entry.metadata()?;
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let mut stats = PruneStats::default();
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in entries {
+            if size <= max_size {
+                break;
+            }
+            remove_entry(&path)?;
+            size -= len;
+            stats.removed += 1;
+            stats.freed += len;
+        }
+        Ok(stats)
+    }
+}
+
+/// Format a root as `0x` prefixed hex, as used in beacon API paths
+pub fn root_hex(root: &Root) -> String {
+    format!("0x{}", alloy::hex::encode(root.to_vec()))
+}
+
+/// Remove an entry, ignoring it if it was already removed by another process
+fn remove_entry(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn parse_entry_fork(data: &[u8]) -> Option<Fork> {
+    let end = data.iter().position(|b| *b == b'\n')?;
+    ssz_files::parse_fork(std::str::from_utf8(&data[..end]).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(byte: u8) -> Root {
+        Root::try_from([byte; 32].as_slice()).unwrap()
+    }
+
+    #[test]
+    fn inserted_entries_are_returned_with_their_fork() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BeaconCache::new(dir.path().to_path_buf(), u64::MAX);
+        cache
+            .insert(CacheKind::Block, &root(1), Fork::Deneb, b"block")
+            .unwrap();
+
+        let (fork, data) = cache.get(CacheKind::Block, &root(1)).unwrap().unwrap();
+        assert_eq!(fork.to_string(), Fork::Deneb.to_string());
+        assert_eq!(data, b"block");
+        assert!(cache.get(CacheKind::State, &root(1)).unwrap().is_none());
+
+        cache.remove(CacheKind::Block, &root(1)).unwrap();
+        assert!(cache.get(CacheKind::Block, &root(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn prune_skips_entries_being_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BeaconCache::new(dir.path().to_path_buf(), u64::MAX);
+        cache
+            .insert(CacheKind::State, &root(1), Fork::Electra, b"state")
+            .unwrap();
+        let tmp = cache.path(CacheKind::State, &root(2)).with_extension("tmp");
+        std::fs::write(&tmp, b"partial").unwrap();
+
+        let stats = cache.prune(0).unwrap();
+        assert_eq!(stats.removed, 1);
+        assert!(tmp.exists());
+        assert!(cache.get(CacheKind::State, &root(1)).unwrap().is_none());
+    }
+}
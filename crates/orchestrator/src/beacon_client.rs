@@ -1,14 +1,53 @@
 /// Retrieve beacon chain data or blocks from an Ethereum 2.0 beacon node.
 use ethereum_consensus::{
-    phase0::SignedBeaconBlockHeader, primitives::Root, types::mainnet::BeaconState, Fork,
+    phase0::{Checkpoint, SignedBeaconBlockHeader},
+    primitives::Root,
+    types::mainnet::{BeaconState, SignedBeaconBlock},
+    Fork,
+};
+use futures::{future::join_all, stream, Stream};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    IntoUrl, StatusCode,
 };
-use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
-use reqwest::IntoUrl;
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Display};
+use ssz_rs::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    sync::Mutex,
+    time::Duration,
+};
 use url::Url;
 
+use crate::{
+    beacon_cache::{root_hex, BeaconCache, CacheKind},
+    beacon_events::{BeaconEvent, EventParser, EventTopic, ServerSentEvent},
+    ssz_files,
+};
+
+/// Accept header for fork versioned objects. SSZ is much faster to decode than JSON for large
+/// objects such as the state, but JSON is accepted from nodes that don't support it
+const ACCEPT_SSZ_OR_JSON: &str = "application/octet-stream;q=1.0,application/json;q=0.9";
+
+/// Header giving the fork of an SSZ response
+const ETH_CONSENSUS_VERSION: &str = "Eth-Consensus-Version";
+
+/// Header asking the server to resume an event stream after the given event
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+/// Number of slots to look back over for a block when slots are missed. This is the length of the
+/// EIP-4788 beacon roots buffer, beyond which the contract could not find the block root either
+const MAX_MISSED_SLOTS: u64 = 8191;
+
+/// Delay before reconnecting to the event stream, unless the server asks for another
+const EVENT_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Time without any data after which the event stream is assumed to have stalled.
+/// Head events arrive every slot so a healthy subscription including them never gets close
+const EVENT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
 /// Errors returned by the [BeaconClient].
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -16,10 +55,49 @@ pub enum Error {
     Url(#[from] url::ParseError),
     #[error("HTTP request failed: {0}")]
     Http(#[from] reqwest::Error),
-    #[error("JSON request middleware failed: {0}")]
-    Middleware(#[from] reqwest_middleware::Error),
     #[error("version field does not match data version")]
     VersionMismatch,
+    #[error("SSZ response has no valid {ETH_CONSENSUS_VERSION} header")]
+    MissingVersion,
+    #[error("unknown consensus version {0}")]
+    UnknownVersion(String),
+    #[error("failed to decode SSZ response: {0}")]
+    Ssz(#[from] DeserializeError),
+    #[error("failed to encode SSZ: {0}")]
+    SszEncode(#[from] SerializeError),
+    #[error("beacon cache failed: {0}")]
+    Cache(#[from] std::io::Error),
+    #[error("failed to compute hash tree root: {0}")]
+    Merkleization(#[from] MerkleizationError),
+    #[error("no beacon endpoints given")]
+    NoEndpoints,
+    #[error("only {responses} beacon endpoints responded but a quorum of {required} is required")]
+    QuorumNotReached { required: usize, responses: usize },
+    #[error("beacon endpoints disagree on the block header root: {0:?}")]
+    QuorumDisagreement(Vec<String>),
+    #[error("failed to decode beacon event: {0}")]
+    Event(#[from] serde_json::Error),
+    #[error("no beacon events received for {0:?}")]
+    EventTimeout(Duration),
+    #[error("beacon event stream closed by the server")]
+    EventStreamClosed,
+    #[error("{kind:?} returned for {expected} has root {actual}")]
+    RootMismatch {
+        kind: CacheKind,
+        expected: String,
+        actual: String,
+    },
+    #[error("header quorum of {quorum} must be between 1 and the {endpoints} beacon endpoints")]
+    InvalidQuorum { quorum: usize, endpoints: usize },
+    #[error("no block found in the {MAX_MISSED_SLOTS} slots up to slot {0}")]
+    NoBlockFound(u64),
+}
+
+impl Error {
+    /// Whether the node reported the requested object does not exist, as for a missed slot
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Http(e) if e.status() == Some(StatusCode::NOT_FOUND))
+    }
 }
 
 /// Response returned by the `get_block_header` API.
@@ -30,6 +108,20 @@ pub struct GetBlockHeaderResponse {
     pub header: SignedBeaconBlockHeader,
 }
 
+/// Response returned by the `get_finality_checkpoints` API.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FinalityCheckpoints {
+    pub previous_justified: Checkpoint,
+    pub current_justified: Checkpoint,
+    pub finalized: Checkpoint,
+}
+
+/// Response returned by the block and state root APIs.
+#[derive(Debug, Serialize, Deserialize)]
+struct RootResponse {
+    root: Root,
+}
+
 /// Wrapper returned by the API calls.
 #[derive(Serialize, Deserialize)]
 struct Response<T> {
@@ -46,49 +138,190 @@ struct VersionedResponse<T> {
     inner: Response<T>,
 }
 
+/// Record of the requests made to an endpoint, used to prefer endpoints that are working
+#[derive(Debug, Clone, Default)]
+pub struct EndpointHealth {
+    pub successes: u64,
+    pub failures: u64,
+    /// Failures since the last success
+    pub consecutive_failures: u64,
+    pub last_error: Option<String>,
+}
+
 /// Simple beacon API client for the `mainnet` preset that can query headers and blocks.
+///
+/// Requests go to the healthiest of a list of endpoints, failing over to the next one on error.
+/// Optionally block headers are fetched from every endpoint and a quorum must agree on the root.
+/// Blocks and states are requested by root, resolving named ids such as `head` first, so they can
+/// be cached without going stale.
 pub struct BeaconClient {
-    http: ClientWithMiddleware,
-    endpoint: Url,
+    http: reqwest::Client,
+    endpoints: Vec<Url>,
+    health: Mutex<Vec<EndpointHealth>>,
+    header_quorum: Option<usize>,
+    cache: Option<BeaconCache>,
 }
 
 impl BeaconClient {
     /// Creates a new beacon endpoint API client.
-    #[allow(unused)]
-    pub fn new<U: IntoUrl>(endpoint: U) -> Result<Self, Error> {
-        let client = reqwest::Client::new();
+    pub fn new<U: IntoUrl>(endpoints: impl IntoIterator<Item = U>) -> Result<Self, Error> {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| endpoint.into_url())
+            .collect::<Result<Vec<_>, _>>()?;
+        if endpoints.is_empty() {
+            return Err(Error::NoEndpoints);
+        }
         Ok(Self {
-            http: client.into(),
-            endpoint: endpoint.into_url()?,
+            http: reqwest::Client::new(),
+            health: Mutex::new(vec![EndpointHealth::default(); endpoints.len()]),
+            endpoints,
+            header_quorum: None,
+            cache: None,
         })
     }
 
-    /// Creates a new beacon endpoint API client with caching.
-    pub fn new_with_cache<U: IntoUrl>(endpoint: U, cache_dir: &str) -> Result<Self, Error> {
-        let client = reqwest::Client::new();
-        let manager = CACacheManager {
-            path: cache_dir.into(),
-        };
-        let cache = Cache(HttpCache {
-            mode: CacheMode::ForceCache,
-            manager,
-            options: HttpCacheOptions::default(),
-        });
-        let client_with_middleware = ClientBuilder::new(client).with(cache).build();
+    /// Cache blocks and states by root
+    pub fn with_cache(mut self, cache: BeaconCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 
-        Ok(Self {
-            http: client_with_middleware,
-            endpoint: endpoint.into_url()?,
-        })
+    /// Require block headers to be fetched from at least `quorum` endpoints that all agree on the
+    /// header root. The quorum must be at least one and no more than the number of endpoints
+    pub fn with_header_quorum(mut self, quorum: Option<usize>) -> Result<Self, Error> {
+        if let Some(quorum) = quorum {
+            if !(1..=self.endpoints.len()).contains(&quorum) {
+                return Err(Error::InvalidQuorum {
+                    quorum,
+                    endpoints: self.endpoints.len(),
+                });
+            }
+        }
+        self.header_quorum = quorum;
+        Ok(self)
+    }
+
+    /// The health of each endpoint, in the order the endpoints were given
+    pub fn health(&self) -> Vec<(Url, EndpointHealth)> {
+        let health = self.health.lock().unwrap();
+        self.endpoints
+            .iter()
+            .cloned()
+            .zip(health.iter().cloned())
+            .collect()
+    }
+
+    /// Endpoint indices to try in order, those that have been failing last
+    fn endpoint_order(&self) -> Vec<usize> {
+        let health = self.health.lock().unwrap();
+        let mut order: Vec<usize> = (0..self.endpoints.len()).collect();
+        order.sort_by_key(|i| health[*i].consecutive_failures);
+        order
+    }
+
+    /// Record the outcome of a request. A not found response is an answer from a working
+    /// endpoint, so it counts as a success
+    fn record<T>(&self, index: usize, result: &Result<T, Error>) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        match result.as_ref().err().filter(|e| !e.is_not_found()) {
+            None => {
+                health.successes += 1;
+                health.consecutive_failures = 0;
+            }
+            Some(e) => {
+                health.failures += 1;
+                health.consecutive_failures += 1;
+                health.last_error = Some(e.to_string());
+                tracing::warn!(
+                    "Beacon endpoint {} failed ({} in a row): {}",
+                    self.endpoints[index],
+                    health.consecutive_failures,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Make a request to each endpoint in turn until one succeeds.
+    /// Returns the error from the last endpoint if they all fail. A not found response is returned
+    /// straight away, as for a missed slot asking the other endpoints would only get the same answer
+    async fn with_failover<'a, T, F, Fut>(&'a self, request: F) -> Result<T, Error>
+    where
+        F: Fn(&'a Url) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut last_error = Error::NoEndpoints;
+        for index in self.endpoint_order() {
+            let result = request(&self.endpoints[index]).await;
+            self.record(index, &result);
+            match result {
+                Ok(value) => return Ok(value),
+                Err(e) if e.is_not_found() => return Err(e),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
     }
 
-    async fn http_get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
-        let target = self.endpoint.join(path)?;
+    async fn http_get<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &Url,
+        path: &str,
+    ) -> Result<T, Error> {
+        let target = endpoint.join(path)?;
         let resp = self.http.get(target).send().await?;
         let value = resp.error_for_status()?.json().await?;
         Ok(value)
     }
 
+    /// Retrieves a fork versioned object, as SSZ if the node supports it or JSON otherwise.
+    /// Returns the fork along with the object
+    async fn http_get_versioned<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &Url,
+        path: &str,
+        from_ssz: fn(&[u8], Fork) -> Result<T, DeserializeError>,
+    ) -> Result<(Fork, T), Error> {
+        let target = endpoint.join(path)?;
+        let mut resp = self
+            .http
+            .get(target.clone())
+            .header(ACCEPT, ACCEPT_SSZ_OR_JSON)
+            .send()
+            .await?;
+        // some nodes reject a weighted accept header rather than picking a format
+        if resp.status() == StatusCode::NOT_ACCEPTABLE {
+            resp = self
+                .http
+                .get(target)
+                .header(ACCEPT, "application/json")
+                .send()
+                .await?;
+        }
+        let resp = resp.error_for_status()?;
+
+        let is_ssz = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"application/octet-stream"));
+        if !is_ssz {
+            let result: VersionedResponse<T> = resp.json().await?;
+            return Ok((result.version, result.inner.data));
+        }
+
+        let version = resp
+            .headers()
+            .get(ETH_CONSENSUS_VERSION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(Error::MissingVersion)?
+            .to_string();
+        let fork = ssz_files::parse_fork(&version).ok_or(Error::UnknownVersion(version))?;
+        let bytes = resp.bytes().await?;
+        Ok((fork, from_ssz(&bytes, fork)?))
+    }
+
     /// Retrieves block details for given block id.
     #[tracing::instrument(skip(self), fields(block_id = %block_id))]
     pub async fn get_block_header(
@@ -96,17 +329,580 @@ impl BeaconClient {
         block_id: impl Display,
     ) -> Result<SignedBeaconBlockHeader, Error> {
         let path = format!("eth/v1/beacon/headers/{block_id}");
-        let result: Response<GetBlockHeaderResponse> = self.http_get(&path).await?;
-        Ok(result.data.header)
+        let Some(quorum) = self.header_quorum else {
+            let result: Response<GetBlockHeaderResponse> = self
+                .with_failover(|endpoint| self.http_get(endpoint, &path))
+                .await?;
+            return Ok(result.data.header);
+        };
+
+        // ask every endpoint at once and compare the roots of the headers they return
+        let results = join_all(self.endpoints.iter().enumerate().map(|(index, endpoint)| {
+            let path = &path;
+            async move {
+                let result = self
+                    .http_get::<Response<GetBlockHeaderResponse>>(endpoint, path)
+                    .await;
+                self.record(index, &result);
+                result
+            }
+        }))
+        .await;
+        let mut headers = Vec::new();
+        let mut not_found = None;
+        let mut not_found_count = 0;
+        for result in results {
+            // endpoints that failed don't count towards the quorum
+            match result {
+                Ok(response) => {
+                    let header = response.data.header;
+                    let root = header.hash_tree_root()?;
+                    headers.push((root, header));
+                }
+                Err(e) if e.is_not_found() => {
+                    not_found_count += 1;
+                    not_found = Some(e);
+                }
+                Err(_) => {}
+            }
+        }
+        // a quorum agreeing there is no block, as for a missed slot, is reported as not found
+        if headers.is_empty() && not_found_count >= quorum {
+            if let Some(e) = not_found {
+                return Err(e);
+            }
+        }
+        if headers.len() < quorum {
+            return Err(Error::QuorumNotReached {
+                required: quorum,
+                responses: headers.len(),
+            });
+        }
+        if headers.iter().any(|(root, _)| *root != headers[0].0) {
+            return Err(Error::QuorumDisagreement(
+                headers
+                    .iter()
+                    .map(|(root, _)| format!("{:?}", root))
+                    .collect(),
+            ));
+        }
+        Ok(headers.swap_remove(0).1)
+    }
+
+    /// Retrieves the header of the latest block at or before the given slot, skipping back over
+    /// missed slots. This is the block whose root EIP-4788 gives for the slot
+    pub async fn get_block_header_at_or_before(
+        &self,
+        slot: u64,
+    ) -> Result<SignedBeaconBlockHeader, Error> {
+        let earliest = slot.saturating_sub(MAX_MISSED_SLOTS);
+        for block_slot in (earliest..=slot).rev() {
+            match self.get_block_header(block_slot).await {
+                Ok(header) => return Ok(header),
+                Err(e) if e.is_not_found() => {
+                    tracing::info!("Slot {} was missed, trying the slot before", block_slot);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::NoBlockFound(slot))
     }
 
+    /// Retrieves the full signed block, including the body, for given block id.
+    #[tracing::instrument(skip(self), fields(block_id = %block_id))]
+    pub async fn get_block(&self, block_id: impl Display) -> Result<SignedBeaconBlock, Error> {
+        let root = self
+            .resolve_root(&block_id.to_string(), "eth/v1/beacon/blocks/{id}/root")
+            .await?;
+        if let Some(block) =
+            self.cache_get(CacheKind::Block, &root, ssz_files::decode_block, block_root)?
+        {
+            return Ok(block);
+        }
+
+        let path = format!("eth/v2/beacon/blocks/{}", root_hex(&root));
+        let path = &path;
+        let block = self
+            .with_failover(|endpoint| async move {
+                let (version, block) = self
+                    .http_get_versioned(endpoint, path, ssz_files::decode_block)
+                    .await?;
+                if version.to_string() != block.version().to_string() {
+                    return Err(Error::VersionMismatch);
+                }
+                check_root(CacheKind::Block, &root, block_root(&block)?)?;
+                Ok(block)
+            })
+            .await?;
+        self.cache_insert(CacheKind::Block, &root, block.version(), &block)?;
+        Ok(block)
+    }
+
+    /// Retrieves the beacon state for given state id.
     #[tracing::instrument(skip(self), fields(state_id = %state_id))]
     pub async fn get_beacon_state(&self, state_id: impl Display) -> Result<BeaconState, Error> {
-        let path = format!("eth/v2/debug/beacon/states/{state_id}");
-        let result: VersionedResponse<BeaconState> = self.http_get(&path).await?;
-        if result.version.to_string() != result.inner.data.version().to_string() {
-            return Err(Error::VersionMismatch);
+        let root = self
+            .resolve_root(&state_id.to_string(), "eth/v1/beacon/states/{id}/root")
+            .await?;
+        if let Some(state) =
+            self.cache_get(CacheKind::State, &root, ssz_files::decode_state, state_root)?
+        {
+            return Ok(state);
+        }
+
+        let path = format!("eth/v2/debug/beacon/states/{}", root_hex(&root));
+        let path = &path;
+        let state = self
+            .with_failover(|endpoint| async move {
+                let (version, state) = self
+                    .http_get_versioned(endpoint, path, ssz_files::decode_state)
+                    .await?;
+                if version.to_string() != state.version().to_string() {
+                    return Err(Error::VersionMismatch);
+                }
+                check_root(CacheKind::State, &root, state_root(&state)?)?;
+                Ok(state)
+            })
+            .await?;
+        self.cache_insert(CacheKind::State, &root, state.version(), &state)?;
+        Ok(state)
+    }
+
+    /// Resolve a block or state id to a root using the given root endpoint.
+    /// Ids that are already roots are returned as is
+    async fn resolve_root(&self, id: &str, root_path: &str) -> Result<Root, Error> {
+        if let Some(root) = parse_root(id) {
+            return Ok(root);
         }
-        Ok(result.inner.data)
+        let path = root_path.replace("{id}", id);
+        let result: Response<RootResponse> = self
+            .with_failover(|endpoint| self.http_get(endpoint, &path))
+            .await?;
+        Ok(result.data.root)
+    }
+
+    /// Look up a cached object. Entries that fail to decode or don't match their root are
+    /// removed and treated as a miss so the object is fetched again
+    fn cache_get<T>(
+        &self,
+        kind: CacheKind,
+        root: &Root,
+        decode: fn(&[u8], Fork) -> Result<T, DeserializeError>,
+        hash: fn(&T) -> Result<Root, MerkleizationError>,
+    ) -> Result<Option<T>, Error> {
+        let Some(cache) = &self.cache else {
+            return Ok(None);
+        };
+        let Some((fork, data)) = cache.get(kind, root)? else {
+            return Ok(None);
+        };
+        let checked = decode(&data, fork).map_err(Error::from).and_then(|value| {
+            check_root(kind, root, hash(&value)?)?;
+            Ok(value)
+        });
+        match checked {
+            Ok(value) => {
+                tracing::debug!("Loaded {:?} {} from the cache", kind, root_hex(root));
+                Ok(Some(value))
+            }
+            Err(e) => {
+                tracing::warn!("Removing bad cache entry: {}", e);
+                cache.remove(kind, root)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Store an object by root. Callers check the object has this root first
+    fn cache_insert<T: ssz_rs::Serialize>(
+        &self,
+        kind: CacheKind,
+        root: &Root,
+        fork: Fork,
+        value: &T,
+    ) -> Result<(), Error> {
+        if let Some(cache) = &self.cache {
+            let mut data = Vec::new();
+            value.serialize(&mut data)?;
+            cache.insert(kind, root, fork, &data)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the finality checkpoints for given state id.
+    /// This changes as the chain progresses so should not be requested through the cache
+    #[tracing::instrument(skip(self), fields(state_id = %state_id))]
+    pub async fn get_finality_checkpoints(
+        &self,
+        state_id: impl Display,
+    ) -> Result<FinalityCheckpoints, Error> {
+        let path = format!("eth/v1/beacon/states/{state_id}/finality_checkpoints");
+        let result: Response<FinalityCheckpoints> = self
+            .with_failover(|endpoint| self.http_get(endpoint, &path))
+            .await?;
+        Ok(result.data)
+    }
+}
+
+/// State carried between items of the event stream
+struct EventStream<'a> {
+    client: &'a BeaconClient,
+    topics: String,
+    response: Option<reqwest::Response>,
+    parser: EventParser,
+    pending: VecDeque<ServerSentEvent>,
+    last_event_id: Option<String>,
+    /// Whether the last connection failed, so the next one waits before it is made
+    reconnecting: bool,
+}
+
+impl BeaconClient {
+    /// Subscribe to events on the given topics.
+    ///
+    /// The subscription reconnects when the connection is lost or stalls, failing over between
+    /// endpoints and asking to resume after the last event received. Errors are yielded as they
+    /// happen and the stream carries on, so it never ends. Nodes that don't support resuming may
+    /// drop events while disconnected, so consumers should check the chain after an error
+    pub fn events(
+        &self,
+        topics: &[EventTopic],
+    ) -> impl Stream<Item = Result<BeaconEvent, Error>> + '_ {
+        let state = EventStream {
+            client: self,
+            topics: topics
+                .iter()
+                .map(EventTopic::as_str)
+                .collect::<Vec<_>>()
+                .join(","),
+            response: None,
+            parser: EventParser::default(),
+            pending: VecDeque::new(),
+            last_event_id: None,
+            reconnecting: false,
+        };
+        stream::unfold(state, |mut state| async move {
+            let item = state.next_event().await;
+            Some((item, state))
+        })
+    }
+
+    /// Open the event stream on the first endpoint that accepts it
+    async fn connect_events(
+        &self,
+        topics: &str,
+        last_event_id: Option<&str>,
+    ) -> Result<reqwest::Response, Error> {
+        self.with_failover(|endpoint| async move {
+            let target = endpoint.join("eth/v1/events")?;
+            let mut request = self
+                .http
+                .get(target)
+                .query(&[("topics", topics)])
+                .header(ACCEPT, "text/event-stream");
+            if let Some(id) = last_event_id {
+                request = request.header(LAST_EVENT_ID, id);
+            }
+            Ok(request.send().await?.error_for_status()?)
+        })
+        .await
+    }
+}
+
+impl EventStream<'_> {
+    async fn next_event(&mut self) -> Result<BeaconEvent, Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                if event.id.is_some() {
+                    self.last_event_id = event.id.clone();
+                }
+                match BeaconEvent::decode(&event)? {
+                    Some(event) => return Ok(event),
+                    None => continue,
+                }
+            }
+
+            let Some(response) = self.response.as_mut() else {
+                if self.reconnecting {
+                    tokio::time::sleep(self.parser.retry().unwrap_or(EVENT_RETRY_DELAY)).await;
+                }
+                self.reconnecting = true;
+                self.parser.reset();
+                let response = self
+                    .client
+                    .connect_events(&self.topics, self.last_event_id.as_deref())
+                    .await?;
+                tracing::debug!("Subscribed to beacon events: {}", self.topics);
+                self.response = Some(response);
+                self.reconnecting = false;
+                continue;
+            };
+
+            let result = match tokio::time::timeout(EVENT_IDLE_TIMEOUT, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    self.pending.extend(self.parser.push(&chunk));
+                    continue;
+                }
+                Ok(Ok(None)) => Error::EventStreamClosed,
+                Ok(Err(e)) => e.into(),
+                Err(_) => Error::EventTimeout(EVENT_IDLE_TIMEOUT),
+            };
+            self.response = None;
+            self.reconnecting = true;
+            return Err(result);
+        }
+    }
+}
+
+/// The block root of a signed block, the hash tree root of its message
+fn block_root(block: &SignedBeaconBlock) -> Result<Root, MerkleizationError> {
+    match block {
+        SignedBeaconBlock::Phase0(block) => block.message.hash_tree_root(),
+        SignedBeaconBlock::Altair(block) => block.message.hash_tree_root(),
+        SignedBeaconBlock::Bellatrix(block) => block.message.hash_tree_root(),
+        SignedBeaconBlock::Capella(block) => block.message.hash_tree_root(),
+        SignedBeaconBlock::Deneb(block) => block.message.hash_tree_root(),
+        SignedBeaconBlock::Electra(block) => block.message.hash_tree_root(),
+    }
+}
+
+fn state_root(state: &BeaconState) -> Result<Root, MerkleizationError> {
+    state.hash_tree_root()
+}
+
+/// Check an object has the root it was requested or cached by
+fn check_root(kind: CacheKind, expected: &Root, actual: Root) -> Result<(), Error> {
+    if actual != *expected {
+        return Err(Error::RootMismatch {
+            kind,
+            expected: root_hex(expected),
+            actual: root_hex(&actual),
+        });
+    }
+    Ok(())
+}
+
+/// Parse a `0x` prefixed hex root
+fn parse_root(id: &str) -> Option<Root> {
+    let bytes = alloy::hex::decode(id.strip_prefix("0x")?).ok()?;
+    Root::try_from(bytes.as_slice()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_consensus::electra;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn client(endpoints: usize) -> BeaconClient {
+        BeaconClient::new((0..endpoints).map(|i| format!("http://127.0.0.1:{}", 5052 + i))).unwrap()
+    }
+
+    fn header_response() -> serde_json::Value {
+        let mut header = SignedBeaconBlockHeader::default();
+        header.message.slot = 7;
+        json!({
+            "data": GetBlockHeaderResponse {
+                root: header.message.hash_tree_root().unwrap(),
+                canonical: true,
+                header,
+            }
+        })
+    }
+
+    fn electra_block() -> (Root, electra::mainnet::SignedBeaconBlock) {
+        let mut block = electra::mainnet::SignedBeaconBlock::default();
+        block.message.slot = 7;
+        (block.message.hash_tree_root().unwrap(), block)
+    }
+
+    fn block_path(root: &Root) -> String {
+        format!("/eth/v2/beacon/blocks/{}", root_hex(root))
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_endpoint() {
+        let failing = MockServer::start().await;
+        let working = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/eth/v1/beacon/headers/head"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&failing)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/eth/v1/beacon/headers/head"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(header_response()))
+            .expect(2)
+            .mount(&working)
+            .await;
+
+        let client = BeaconClient::new([failing.uri(), working.uri()]).unwrap();
+        assert_eq!(
+            client.get_block_header("head").await.unwrap().message.slot,
+            7
+        );
+        // the failing endpoint is now tried last, so isn't asked again
+        assert_eq!(
+            client.get_block_header("head").await.unwrap().message.slot,
+            7
+        );
+
+        let health = client.health();
+        assert_eq!(health[0].1.failures, 1);
+        assert_eq!(health[0].1.consecutive_failures, 1);
+        assert!(health[0].1.last_error.is_some());
+        assert_eq!(health[1].1.successes, 2);
+        assert_eq!(health[1].1.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn not_found_is_returned_without_failing_over() {
+        let missing = MockServer::start().await;
+        let other = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/eth/v1/beacon/headers/100"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&missing)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(header_response()))
+            .expect(0)
+            .mount(&other)
+            .await;
+
+        let client = BeaconClient::new([missing.uri(), other.uri()]).unwrap();
+        let err = client.get_block_header(100).await.unwrap_err();
+        assert!(err.is_not_found(), "{:?}", err);
+
+        let health = client.health();
+        assert_eq!(health[0].1.failures, 0);
+        assert_eq!(health[0].1.consecutive_failures, 0);
+        assert_eq!(health[1].1.successes + health[1].1.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn decodes_ssz_blocks() {
+        let (root, block) = electra_block();
+        let mut data = Vec::new();
+        block.serialize(&mut data).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(block_path(&root)))
+            .and(header(ACCEPT, ACCEPT_SSZ_OR_JSON))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(CONTENT_TYPE, "application/octet-stream")
+                    .insert_header(ETH_CONSENSUS_VERSION, "electra")
+                    .set_body_bytes(data),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BeaconClient::new([server.uri()]).unwrap();
+        let fetched = client.get_block(root_hex(&root)).await.unwrap();
+        assert!(matches!(fetched, SignedBeaconBlock::Electra(_)));
+        assert_eq!(block_root(&fetched).unwrap(), root);
+    }
+
+    #[tokio::test]
+    async fn rejects_ssz_without_a_known_version() {
+        let (root, block) = electra_block();
+        let mut data = Vec::new();
+        block.serialize(&mut data).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(block_path(&root)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(CONTENT_TYPE, "application/octet-stream")
+                    .set_body_bytes(data),
+            )
+            .mount(&server)
+            .await;
+
+        let client = BeaconClient::new([server.uri()]).unwrap();
+        let err = client.get_block(root_hex(&root)).await.unwrap_err();
+        assert!(matches!(err, Error::MissingVersion), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_json_when_ssz_is_not_acceptable() {
+        let (root, block) = electra_block();
+        let server = MockServer::start().await;
+        // mounted first so it takes priority for the plain JSON request
+        Mock::given(method("GET"))
+            .and(path(block_path(&root)))
+            .and(header(ACCEPT, "application/json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "version": Fork::Electra, "data": block })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(block_path(&root)))
+            .respond_with(ResponseTemplate::new(406))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BeaconClient::new([server.uri()]).unwrap();
+        let fetched = client.get_block(root_hex(&root)).await.unwrap();
+        assert!(matches!(fetched, SignedBeaconBlock::Electra(_)));
+        assert_eq!(block_root(&fetched).unwrap(), root);
+    }
+
+    #[tokio::test]
+    async fn accepts_json_from_nodes_without_ssz_support() {
+        let (root, block) = electra_block();
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(block_path(&root)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "version": Fork::Electra, "data": block })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BeaconClient::new([server.uri()]).unwrap();
+        let fetched = client.get_block(root_hex(&root)).await.unwrap();
+        assert_eq!(block_root(&fetched).unwrap(), root);
+    }
+
+    #[test]
+    fn rejects_quorum_outside_the_endpoints() {
+        assert!(matches!(
+            client(2).with_header_quorum(Some(0)),
+            Err(Error::InvalidQuorum {
+                quorum: 0,
+                endpoints: 2
+            })
+        ));
+        assert!(matches!(
+            client(2).with_header_quorum(Some(3)),
+            Err(Error::InvalidQuorum {
+                quorum: 3,
+                endpoints: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn accepts_quorum_within_the_endpoints() {
+        assert!(client(2).with_header_quorum(None).is_ok());
+        assert!(client(2).with_header_quorum(Some(1)).is_ok());
+        assert!(client(2).with_header_quorum(Some(2)).is_ok());
     }
 }
@@ -0,0 +1,313 @@
+//! Typed events from the beacon node event stream, `/eth/v1/events`
+//!
+//! The stream is served as server-sent events. Each event carries its topic as the event type and
+//! a JSON object as its data. Only the topics the orchestrator reacts to are decoded.
+
+use std::{fmt, time::Duration};
+
+use ethereum_consensus::primitives::{Epoch, Root, Slot};
+use serde::{Deserialize, Serialize};
+
+/// Topics that can be subscribed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTopic {
+    Head,
+    FinalizedCheckpoint,
+    ChainReorg,
+}
+
+impl EventTopic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventTopic::Head => "head",
+            EventTopic::FinalizedCheckpoint => "finalized_checkpoint",
+            EventTopic::ChainReorg => "chain_reorg",
+        }
+    }
+}
+
+impl fmt::Display for EventTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The node has a new head block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeadEvent {
+    #[serde(with = "ethereum_consensus::serde::as_str")]
+    pub slot: Slot,
+    pub block: Root,
+    pub state: Root,
+    pub epoch_transition: bool,
+    #[serde(default)]
+    pub execution_optimistic: bool,
+}
+
+/// A new checkpoint has been finalized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalizedCheckpointEvent {
+    pub block: Root,
+    pub state: Root,
+    #[serde(with = "ethereum_consensus::serde::as_str")]
+    pub epoch: Epoch,
+    #[serde(default)]
+    pub execution_optimistic: bool,
+}
+
+/// The head has switched to a block that does not descend from the previous head
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainReorgEvent {
+    #[serde(with = "ethereum_consensus::serde::as_str")]
+    pub slot: Slot,
+    #[serde(with = "ethereum_consensus::serde::as_str")]
+    pub depth: u64,
+    pub old_head_block: Root,
+    pub new_head_block: Root,
+    #[serde(with = "ethereum_consensus::serde::as_str")]
+    pub epoch: Epoch,
+    #[serde(default)]
+    pub execution_optimistic: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum BeaconEvent {
+    Head(HeadEvent),
+    FinalizedCheckpoint(FinalizedCheckpointEvent),
+    ChainReorg(ChainReorgEvent),
+}
+
+impl BeaconEvent {
+    /// Decode a server-sent event. Returns `None` for topics that are not decoded
+    pub fn decode(event: &ServerSentEvent) -> Result<Option<Self>, serde_json::Error> {
+        let event = match event.event.as_str() {
+            "head" => BeaconEvent::Head(serde_json::from_str(&event.data)?),
+            "finalized_checkpoint" => {
+                BeaconEvent::FinalizedCheckpoint(serde_json::from_str(&event.data)?)
+            }
+            "chain_reorg" => BeaconEvent::ChainReorg(serde_json::from_str(&event.data)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+/// A single event as sent by the server, before its data is decoded
+#[derive(Debug, Clone, Default)]
+pub struct ServerSentEvent {
+    pub event: String,
+    pub data: String,
+    /// The last event id the server sent, to resume from when reconnecting
+    pub id: Option<String>,
+}
+
+/// Incremental parser for a `text/event-stream` body, fed chunks as they arrive
+#[derive(Debug, Default)]
+pub struct EventParser {
+    buffer: Vec<u8>,
+    event: String,
+    data: String,
+    last_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl EventParser {
+    /// Parse a chunk of the body, returning the events completed by it
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<ServerSentEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            events.extend(self.line(line));
+        }
+        events
+    }
+
+    /// Reconnection delay requested by the server, if any
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Drop any partly received event, as when the connection is lost
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.event.clear();
+        self.data.clear();
+    }
+
+    fn line(&mut self, line: &str) -> Option<ServerSentEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // lines starting with a colon are comments, used by servers as keep alives
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<ServerSentEvent> {
+        let mut event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        if event.is_empty() {
+            event = "message".to_string();
+        }
+        Some(ServerSentEvent {
+            event,
+            data,
+            id: self.last_id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINALIZED: &str = r#"{"block":"0x9a2fefd2fdb57f74993c7780ea5b9030d2897b615b89f808011ca5aebed54eaf","state":"0x600e852a08c1200654ddf11025f1ceacb3c2e74bdd5c630cde0838b2591b69f9","epoch":"2","execution_optimistic":false}"#;
+
+    fn parse(chunks: &[&str]) -> (EventParser, Vec<ServerSentEvent>) {
+        let mut parser = EventParser::default();
+        let events = chunks
+            .iter()
+            .flat_map(|chunk| parser.push(chunk.as_bytes()))
+            .collect();
+        (parser, events)
+    }
+
+    #[test]
+    fn parses_a_single_event() {
+        let (_, events) = parse(&["event: head\ndata: {\"a\":1}\n\n"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "head");
+        assert_eq!(events[0].data, "{\"a\":1}");
+        assert_eq!(events[0].id, None);
+    }
+
+    #[test]
+    fn joins_multi_line_data() {
+        let (_, events) = parse(&["event: x\ndata: first\ndata:second\ndata\n\n"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "first\nsecond\n");
+    }
+
+    #[test]
+    fn handles_crlf_line_endings() {
+        let (_, events) =
+            parse(&["event: head\r\ndata: one\r\n\r\nevent: head\r\ndata: two\r\n\r\n"]);
+        let data: Vec<_> = events.iter().map(|e| e.data.as_str()).collect();
+        assert_eq!(data, ["one", "two"]);
+        assert_eq!(events[0].event, "head");
+    }
+
+    #[test]
+    fn ignores_comments_and_keep_alives() {
+        let (_, events) = parse(&[
+            ": keep alive\n\n",
+            ":\n",
+            "event: head\n: comment\ndata: one\n\n",
+        ]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "one");
+    }
+
+    #[test]
+    fn events_without_data_are_not_dispatched() {
+        let (_, events) = parse(&["event: head\n\n", "data: one\n\n"]);
+        assert_eq!(events.len(), 1);
+        // the event type is reset by the blank line even though nothing was dispatched
+        assert_eq!(events[0].event, "message");
+    }
+
+    #[test]
+    fn reassembles_events_split_across_chunks() {
+        let body = format!("event: finalized_checkpoint\r\ndata: {}\r\n\r\n", FINALIZED);
+        for split in 1..body.len() {
+            let (_, events) = parse(&[&body[..split], &body[split..]]);
+            assert_eq!(events.len(), 1, "split at {}", split);
+            assert_eq!(events[0].event, "finalized_checkpoint");
+            assert_eq!(events[0].data, FINALIZED);
+        }
+
+        // and one byte at a time
+        let chars: Vec<String> = body.chars().map(|c| c.to_string()).collect();
+        let chunks: Vec<&str> = chars.iter().map(|s| s.as_str()).collect();
+        let (_, events) = parse(&chunks);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn tracks_the_last_event_id() {
+        let (_, events) = parse(&[
+            "id: 1\ndata: one\n\n",
+            "data: two\n\n",
+            "id: 3\ndata: three\n\n",
+            "id: bad\0id\ndata: four\n\n",
+        ]);
+        let ids: Vec<_> = events.iter().map(|e| e.id.as_deref()).collect();
+        // the id persists until replaced and ids containing NUL are ignored
+        assert_eq!(ids, [Some("1"), Some("1"), Some("3"), Some("3")]);
+    }
+
+    #[test]
+    fn parses_retry_delays() {
+        let (parser, _) = parse(&["retry: 1500\n\n"]);
+        assert_eq!(parser.retry(), Some(Duration::from_millis(1500)));
+
+        let (parser, _) = parse(&["retry: 1500\nretry: soon\n\n"]);
+        assert_eq!(parser.retry(), Some(Duration::from_millis(1500)));
+
+        let (parser, _) = parse(&["retry: soon\n\n"]);
+        assert_eq!(parser.retry(), None);
+    }
+
+    #[test]
+    fn reset_drops_partial_events_but_keeps_the_id() {
+        let mut parser = EventParser::default();
+        parser.push(b"id: 7\ndata: done\n\nevent: head\ndata: partial\ndata: more");
+        parser.reset();
+        let events = parser.push(b"data: fresh\n\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "message");
+        assert_eq!(events[0].data, "fresh");
+        assert_eq!(events[0].id.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn decodes_known_topics() {
+        let body = format!(
+            "event: finalized_checkpoint\ndata: {}\n\nevent: block\ndata: {{}}\n\n",
+            FINALIZED
+        );
+        let (_, events) = parse(&[body.as_str()]);
+        let Some(BeaconEvent::FinalizedCheckpoint(checkpoint)) =
+            BeaconEvent::decode(&events[0]).unwrap()
+        else {
+            panic!("expected a finalized checkpoint event");
+        };
+        assert_eq!(checkpoint.epoch, 2);
+        assert!(BeaconEvent::decode(&events[1]).unwrap().is_none());
+    }
+}
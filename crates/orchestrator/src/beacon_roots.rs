@@ -0,0 +1,224 @@
+//! Looking up beacon block roots from the EIP-4788 beacon roots contract
+//!
+//! This mirrors `BeaconBlockRoots.findBlockRoot` in the contracts so the orchestrator can check
+//! the root the contract will be given before it submits a trusted report request.
+
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{address, Address, Bytes, B256, U256},
+    providers::Provider,
+    rpc::{json_rpc::ErrorPayload, types::TransactionRequest},
+    sol_types::SolValue,
+    transports::{RpcError, TransportError},
+};
+use thiserror::Error;
+
+/// The address of the beacon roots contract (regardless of chain)
+pub const BEACON_ROOTS: Address = address!("000F3df6D732807Ef1319fB7B8bB8522d0Beac02");
+
+/// The length of the beacon roots ring buffer
+pub const BEACON_ROOTS_HISTORY_BUFFER_LENGTH: u64 = 8191;
+
+/// Seconds per slot assumed by the contract
+const SECONDS_PER_SLOT: u64 = 12;
+
+/// Slots to keep clear of the start of the window, so the root is still available when the
+/// transaction is included a little later than the block the check was made at
+const WINDOW_MARGIN_SLOTS: u64 = 32;
+
+#[derive(Error, Debug)]
+pub enum BeaconRootsError {
+    #[error("slot {slot} is outside the window of the last {BEACON_ROOTS_HISTORY_BUFFER_LENGTH} beacon roots")]
+    TimestampOutOfRange { slot: u64 },
+    #[error("no beacon root found for slot {slot}")]
+    NoBlockRootFound { slot: u64 },
+    #[error("request to the beacon roots contract failed: {0}")]
+    Transport(#[from] TransportError),
+}
+
+/// Find the block root the contract will use for a slot as of the latest block.
+///
+/// The beacon roots contract maps the timestamp of a block to the root of its parent, so the root
+/// for slot N is looked up by the timestamp of slot N+1. If that slot was missed the next one is
+/// tried, and so on up to the latest block
+pub async fn find_block_root<P: Provider>(
+    provider: &P,
+    genesis_block_timestamp: u64,
+    slot: u64,
+) -> Result<B256, BeaconRootsError> {
+    let latest = provider
+        .get_block_by_number(BlockNumberOrTag::Latest)
+        .await?
+        .ok_or(BeaconRootsError::NoBlockRootFound { slot })?
+        .header
+        .timestamp;
+
+    let mut timestamp = genesis_block_timestamp + (slot + 1) * SECONDS_PER_SLOT;
+    let earliest = latest.saturating_sub(
+        (BEACON_ROOTS_HISTORY_BUFFER_LENGTH - WINDOW_MARGIN_SLOTS) * SECONDS_PER_SLOT,
+    );
+    if timestamp <= earliest {
+        return Err(BeaconRootsError::TimestampOutOfRange { slot });
+    }
+
+    while timestamp <= latest {
+        let tx = TransactionRequest::default()
+            .to(BEACON_ROOTS)
+            .input(Bytes::from(U256::from(timestamp).abi_encode()).into());
+        match provider.call(&tx).await {
+            Ok(result) if result.len() == 32 => return Ok(B256::from_slice(&result)),
+            Ok(_) => {}
+            // the contract reverts for timestamps without a block
+            Err(RpcError::ErrorResp(e)) if is_revert(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        timestamp += SECONDS_PER_SLOT;
+    }
+
+    Err(BeaconRootsError::NoBlockRootFound { slot })
+}
+
+/// Whether an error response is the call reverting rather than the node failing. Nodes report a
+/// revert without data with their own error code, so this goes by the message
+fn is_revert(e: &ErrorPayload) -> bool {
+    e.code == 3 || e.message.to_lowercase().contains("execution reverted")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy::{providers::ProviderBuilder, rpc::types::Block};
+    use serde_json::{json, Value};
+    use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    use super::*;
+
+    const GENESIS: u64 = 1_606_824_023;
+
+    /// Timestamp of the block at a slot
+    fn timestamp(slot: u64) -> u64 {
+        GENESIS + slot * SECONDS_PER_SLOT
+    }
+
+    /// An execution node with the beacon roots contract holding `roots` by block timestamp
+    struct Node {
+        latest: u64,
+        roots: HashMap<u64, B256>,
+        call_error: Option<Value>,
+    }
+
+    impl Node {
+        fn new(latest_slot: u64) -> Self {
+            Self {
+                latest: timestamp(latest_slot),
+                roots: HashMap::new(),
+                call_error: None,
+            }
+        }
+
+        /// Set the root the contract gives for the parent of the block at `slot`
+        fn with_root(mut self, slot: u64, root: B256) -> Self {
+            self.roots.insert(timestamp(slot), root);
+            self
+        }
+
+        fn call(&self, params: &Value) -> Result<Value, Value> {
+            if let Some(error) = &self.call_error {
+                return Err(error.clone());
+            }
+            let tx = &params[0];
+            let input = tx.get("input").unwrap_or(&tx["data"]).as_str().unwrap();
+            let timestamp = U256::from_str_radix(input.trim_start_matches("0x"), 16).unwrap();
+            match self.roots.get(&timestamp.to::<u64>()) {
+                Some(root) => Ok(json!(root)),
+                None => Err(json!({ "code": -32000, "message": "execution reverted" })),
+            }
+        }
+    }
+
+    impl Respond for Node {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let request: Value = serde_json::from_slice(&request.body).unwrap();
+            let result = match request["method"].as_str().unwrap() {
+                "eth_getBlockByNumber" => {
+                    let mut block = Block::default();
+                    block.header.inner.timestamp = self.latest;
+                    Ok(serde_json::to_value(block).unwrap())
+                }
+                "eth_call" => self.call(&request["params"]),
+                method => panic!("unexpected request {}", method),
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                Err(error) => json!({ "jsonrpc": "2.0", "id": request["id"], "error": error }),
+            };
+            ResponseTemplate::new(200).set_body_json(response)
+        }
+    }
+
+    async fn find(node: Node, slot: u64) -> Result<B256, BeaconRootsError> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(node)
+            .mount(&server)
+            .await;
+        let provider = ProviderBuilder::new().on_http(server.uri().parse().unwrap());
+        find_block_root(&provider, GENESIS, slot).await
+    }
+
+    #[tokio::test]
+    async fn finds_the_root_from_the_next_block() {
+        let root = B256::repeat_byte(1);
+        let node = Node::new(200).with_root(101, root);
+        assert_eq!(find(node, 100).await.unwrap(), root);
+    }
+
+    #[tokio::test]
+    async fn skips_missed_slots() {
+        // slots 101 and 102 were missed so the root of 100 is given for the block at 103
+        let root = B256::repeat_byte(2);
+        let node = Node::new(200)
+            .with_root(103, root)
+            .with_root(104, B256::ZERO);
+        assert_eq!(find(node, 100).await.unwrap(), root);
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_block_up_to_the_latest() {
+        let node = Node::new(105).with_root(106, B256::repeat_byte(3));
+        assert!(matches!(
+            find(node, 100).await,
+            Err(BeaconRootsError::NoBlockRootFound { slot: 100 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_slots_outside_the_ring_buffer() {
+        let latest = 100 + BEACON_ROOTS_HISTORY_BUFFER_LENGTH;
+        let node = Node::new(latest).with_root(101, B256::repeat_byte(4));
+        assert!(matches!(
+            find(node, 100).await,
+            Err(BeaconRootsError::TimestampOutOfRange { slot: 100 })
+        ));
+
+        // slots close to leaving the window are rejected too
+        let node = Node::new(latest).with_root(130, B256::repeat_byte(4));
+        assert!(matches!(
+            find(node, 129).await,
+            Err(BeaconRootsError::TimestampOutOfRange { slot: 129 })
+        ));
+        let node = Node::new(latest).with_root(101 + WINDOW_MARGIN_SLOTS, B256::repeat_byte(4));
+        assert!(find(node, 100 + WINDOW_MARGIN_SLOTS).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn propagates_other_rpc_errors() {
+        let mut node = Node::new(200).with_root(101, B256::repeat_byte(5));
+        node.call_error = Some(json!({ "code": -32603, "message": "internal error" }));
+        assert!(matches!(
+            find(node, 100).await,
+            Err(BeaconRootsError::Transport(RpcError::ErrorResp(e))) if e.code == -32603
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//! Following Lido's reporting frames to generate a report for each one as it is finalized
+//!
+//! Lido's HashConsensus contract divides the beacon chain into frames of `epochs_per_frame` epochs
+//! starting from `initial_epoch`. The report for a frame is taken at its reference slot, the last
+//! slot before the frame starts, and can be generated once that slot is finalized. Submitted
+//! frames are recorded in a state file so a restarted daemon never submits a frame twice.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use alloy::{
+    primitives::{Address, B256},
+    providers::ProviderBuilder,
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+alloy::sol!(
+    #[sol(rpc)]
+    interface IHashConsensus {
+        function getFrameConfig() external view returns (uint256 initialEpoch, uint256 epochsPerFrame, uint256 fastLaneLengthSlots);
+    }
+);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FrameArgs {
+    /// Address of the Lido HashConsensus contract for the AccountingOracle to read the frame
+    /// config from. Requires the execution rpc url
+    #[clap(long, env, required_unless_present_all(["initial_epoch", "epochs_per_frame"]))]
+    pub hash_consensus_address: Option<Address>,
+
+    /// First epoch of the first reporting frame, instead of reading it from HashConsensus
+    #[clap(long, env, requires("epochs_per_frame"))]
+    pub initial_epoch: Option<u64>,
+
+    /// Number of epochs in each reporting frame, instead of reading it from HashConsensus
+    #[clap(long, env, requires("initial_epoch"))]
+    pub epochs_per_frame: Option<u64>,
+}
+
+/// Layout of the reporting frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameConfig {
+    pub initial_epoch: u64,
+    pub epochs_per_frame: u64,
+    pub slots_per_epoch: u64,
+}
+
+impl FrameConfig {
+    /// Resolve the frame config from the args, falling back to the HashConsensus contract
+    pub async fn resolve(
+        args: &FrameArgs,
+        execution_rpc_url: Option<Url>,
+        slots_per_epoch: u64,
+    ) -> Result<Self> {
+        let (initial_epoch, epochs_per_frame) = match (args.initial_epoch, args.epochs_per_frame) {
+            (Some(initial_epoch), Some(epochs_per_frame)) => (initial_epoch, epochs_per_frame),
+            _ => {
+                let address = args
+                    .hash_consensus_address
+                    .context("a HashConsensus address or frame config is required")?;
+                let execution_rpc_url = execution_rpc_url
+                    .context("an execution rpc url is required to read the frame config")?;
+                let provider = ProviderBuilder::new().on_http(execution_rpc_url);
+                let config = IHashConsensus::new(address, provider)
+                    .getFrameConfig()
+                    .call()
+                    .await?;
+                (
+                    config.initialEpoch.try_into()?,
+                    config.epochsPerFrame.try_into()?,
+                )
+            }
+        };
+        if epochs_per_frame == 0 || initial_epoch == 0 {
+            bail!("Invalid frame config: initial epoch and epochs per frame must be non-zero");
+        }
+        Ok(Self {
+            initial_epoch,
+            epochs_per_frame,
+            slots_per_epoch,
+        })
+    }
+
+    /// The reference slot of a frame, the last slot before the frame starts
+    pub fn ref_slot(&self, frame: u64) -> u64 {
+        (self.initial_epoch + frame * self.epochs_per_frame) * self.slots_per_epoch - 1
+    }
+
+    /// The latest frame whose reference slot is finalized when the given epoch is finalized.
+    /// Returns None if the first frame has not started yet
+    pub fn latest_finalized_frame(&self, finalized_epoch: u64) -> Option<u64> {
+        finalized_epoch
+            .checked_sub(self.initial_epoch)
+            .map(|epochs| epochs / self.epochs_per_frame)
+    }
+}
+
+/// Progress of a frame's report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameStatus {
+    /// The transaction is being sent. If the daemon stopped before it was sent this stays recorded
+    /// and the frame is not retried automatically, as it cannot tell whether it was sent
+    Submitting,
+    Submitted {
+        tx_hash: B256,
+    },
+}
+
+/// Frames submitted by the daemon, persisted between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DaemonState {
+    /// Status of each frame by reference slot
+    pub frames: BTreeMap<u64, FrameStatus>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl DaemonState {
+    /// Load the state file, starting afresh if it does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut state: Self = if path.exists() {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("failed to open daemon state {:?}", path))?;
+            serde_json::from_reader(file)
+                .with_context(|| format!("failed to parse daemon state {:?}", path))?
+        } else {
+            Self::default()
+        };
+        state.path = path.to_path_buf();
+        Ok(state)
+    }
+
+    pub fn status(&self, ref_slot: u64) -> Option<&FrameStatus> {
+        self.frames.get(&ref_slot)
+    }
+
+    /// Record the status of a frame and write the state file
+    pub fn set_status(&mut self, ref_slot: u64, status: FrameStatus) -> Result<()> {
+        self.frames.insert(ref_slot, status);
+        self.save()
+    }
+
+    /// Forget a frame so it is retried, e.g. after its transaction failed to send
+    pub fn clear_status(&mut self, ref_slot: u64) -> Result<()> {
+        self.frames.remove(&ref_slot);
+        self.save()
+    }
+
+    /// Write the state file. It is replaced atomically so a crash never leaves it half written
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write daemon state {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: FrameConfig = FrameConfig {
+        initial_epoch: 10,
+        epochs_per_frame: 225,
+        slots_per_epoch: 32,
+    };
+
+    #[test]
+    fn ref_slot_is_the_last_slot_before_the_frame() {
+        assert_eq!(FRAMES.ref_slot(0), 10 * 32 - 1);
+        assert_eq!(FRAMES.ref_slot(1), 235 * 32 - 1);
+        assert_eq!(FRAMES.ref_slot(2), 460 * 32 - 1);
+    }
+
+    #[test]
+    fn no_frame_is_finalized_before_the_initial_epoch() {
+        assert_eq!(FRAMES.latest_finalized_frame(0), None);
+        assert_eq!(FRAMES.latest_finalized_frame(9), None);
+    }
+
+    #[test]
+    fn frames_are_finalized_at_their_boundary_epochs() {
+        assert_eq!(FRAMES.latest_finalized_frame(10), Some(0));
+        assert_eq!(FRAMES.latest_finalized_frame(234), Some(0));
+        assert_eq!(FRAMES.latest_finalized_frame(235), Some(1));
+        assert_eq!(FRAMES.latest_finalized_frame(459), Some(1));
+        assert_eq!(FRAMES.latest_finalized_frame(460), Some(2));
+    }
+
+    #[test]
+    fn latest_finalized_frame_has_a_finalized_ref_slot() {
+        // a finalized checkpoint at epoch E finalizes every slot before the start of E
+        for epoch in 10..1000 {
+            let frame = FRAMES.latest_finalized_frame(epoch).unwrap();
+            let first_unfinalized_slot = epoch * FRAMES.slots_per_epoch;
+            assert!(FRAMES.ref_slot(frame) < first_unfinalized_slot);
+            assert!(FRAMES.ref_slot(frame + 1) >= first_unfinalized_slot);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Fetching the beacon chain and execution data a report is generated from, and storing it on
+//! disk so later stages can be rerun without fetching it again
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use alloy::{
+    primitives::Address,
+    providers::{Provider, ProviderBuilder},
+};
+use anyhow::{bail, Context, Result};
+use ethereum_consensus::{
+    phase0::{BeaconBlockHeader, SignedBeaconBlockHeader},
+    types::mainnet::{BeaconBlockBody, BeaconState, SignedBeaconBlock},
+};
+use io::{execution_payload, AccountProof, NetworkConfig};
+use serde::{de::DeserializeOwned, Serialize};
+use ssz_rs::prelude::*;
+use url::Url;
+
+use crate::{beacon_cache::root_hex, beacon_client::BeaconClient, ssz_files};
+
+const HEADER_FILE: &str = "header.ssz";
+const BLOCK_FILE: &str = "block.ssz";
+const STATE_FILE: &str = "state.ssz";
+/// Name of the fork the block and state belong to, which SSZ does not record
+const FORK_FILE: &str = "fork";
+const WITHDRAWAL_VAULT_PROOF_FILE: &str = "withdrawal_vault_proof.json";
+
+/// Everything fetched from the beacon and execution nodes for a slot
+#[derive(Debug)]
+pub struct Fetched {
+    pub header: SignedBeaconBlockHeader,
+    pub block: SignedBeaconBlock,
+    pub state: BeaconState,
+    /// Proof of the withdrawal vault account at the execution block of the block
+    pub withdrawal_vault_proof: AccountProof,
+}
+
+impl Fetched {
+    /// Fetch the block, state and withdrawal vault proof for a slot. If the slot was missed the
+    /// latest block before it is used, which is the block root the contract finds for the slot.
+    /// The block body is checked against the header and the proof against the execution state root
+    pub async fn fetch(
+        beacon_client: &BeaconClient,
+        execution_rpc_url: Url,
+        network: &NetworkConfig,
+        slot: u64,
+    ) -> Result<Self> {
+        tracing::info!("Fetching beacon block for slot {}", slot);
+        let header = beacon_client.get_block_header_at_or_before(slot).await?;
+        if header.message.slot != slot {
+            tracing::warn!(
+                "Slot {} was missed, using the block at slot {} as the contract does",
+                slot,
+                header.message.slot
+            );
+        }
+        let block_root = header.message.hash_tree_root()?;
+        let block = beacon_client.get_block(root_hex(&block_root)).await?;
+        let fetched_body = block_body(block.clone());
+        if fetched_body.hash_tree_root()? != header.message.body_root {
+            bail!("Block body does not match the header body root");
+        }
+        // the state after the block, rather than at the requested slot if that was missed
+        tracing::info!("Fetching beacon state for slot {}", header.message.slot);
+        let state = beacon_client
+            .get_beacon_state(root_hex(&header.message.state_root))
+            .await?;
+        if state.hash_tree_root()? != header.message.state_root {
+            bail!("State does not match the header state root");
+        }
+
+        let withdrawal_vault_proof =
+            fetch_withdrawal_vault_proof(execution_rpc_url, network, &fetched_body).await?;
+
+        Ok(Self {
+            header,
+            block,
+            state,
+            withdrawal_vault_proof,
+        })
+    }
+
+    /// Load the state and block from local SSZ files rather than fetching them. If no header file is
+    /// given the header is derived from the block. The withdrawal vault proof is still fetched from
+    /// the execution node as the coprocessor requires it
+    pub async fn from_ssz_files(
+        state: &Path,
+        block: &Path,
+        header: Option<&Path>,
+        execution_rpc_url: Url,
+        network: &NetworkConfig,
+    ) -> Result<Self> {
+        let (header, block, state) = load_ssz_files(state, block, header, network)?;
+        let withdrawal_vault_proof =
+            fetch_withdrawal_vault_proof(execution_rpc_url, network, &block_body(block.clone()))
+                .await?;
+
+        Ok(Self {
+            header,
+            block,
+            state,
+            withdrawal_vault_proof,
+        })
+    }
+
+    pub fn block_body(&self) -> BeaconBlockBody {
+        block_body(self.block.clone())
+    }
+
+    /// Write the fetched data to a directory, creating it if needed. The header, block and state
+    /// are stored as SSZ along with the name of their fork
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {:?}", dir))?;
+        let fork = self.state.version();
+        if self.block.version().to_string() != fork.to_string() {
+            bail!(
+                "Block is from the {} fork but the state is from {}",
+                self.block.version(),
+                fork
+            );
+        }
+        write_file(&dir.join(FORK_FILE), fork.to_string().as_bytes())?;
+        write_ssz(&dir.join(HEADER_FILE), &self.header)?;
+        write_ssz(&dir.join(BLOCK_FILE), &self.block)?;
+        write_ssz(&dir.join(STATE_FILE), &self.state)?;
+        write_json(
+            &dir.join(WITHDRAWAL_VAULT_PROOF_FILE),
+            &self.withdrawal_vault_proof,
+        )?;
+        Ok(())
+    }
+
+    /// Load data written by [Fetched::save]
+    pub fn load(dir: &Path) -> Result<Self> {
+        let fork_path = dir.join(FORK_FILE);
+        let fork = String::from_utf8(read_file(&fork_path)?)?;
+        let fork = ssz_files::parse_fork(&fork)
+            .with_context(|| format!("unknown fork {:?} in {:?}", fork, fork_path))?;
+        let header = SignedBeaconBlockHeader::deserialize(&read_file(&dir.join(HEADER_FILE))?)?;
+        let block = ssz_files::decode_block(&read_file(&dir.join(BLOCK_FILE))?, fork)?;
+        let state = ssz_files::decode_state(&read_file(&dir.join(STATE_FILE))?, fork)?;
+        let withdrawal_vault_proof = read_json(&dir.join(WITHDRAWAL_VAULT_PROOF_FILE))?;
+        Ok(Self {
+            header,
+            block,
+            state,
+            withdrawal_vault_proof,
+        })
+    }
+}
+
+/// Load the header, block and state from SSZ files, checking they belong together
+fn load_ssz_files(
+    state: &Path,
+    block: &Path,
+    header: Option<&Path>,
+    network: &NetworkConfig,
+) -> Result<(SignedBeaconBlockHeader, SignedBeaconBlock, BeaconState)> {
+    let block = ssz_files::load_block(block, network)?;
+    let block_header = block_header(&block)?;
+    let header = match header {
+        Some(path) => {
+            let header = ssz_files::load_header(path)?;
+            if header.message != block_header.message {
+                bail!("Header does not match the block");
+            }
+            header
+        }
+        None => block_header,
+    };
+    let state = ssz_files::load_state(state, network)?;
+
+    if header.message.slot != state.slot() {
+        bail!(
+            "Block is for slot {} but the state is for slot {}",
+            header.message.slot,
+            state.slot()
+        );
+    }
+    if state.hash_tree_root()? != header.message.state_root {
+        bail!("State does not match the header state root");
+    }
+    Ok((header, block, state))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    std::fs::write(path, data).with_context(|| format!("failed to write {:?}", path))
+}
+
+fn write_ssz<T: ssz_rs::Serialize>(path: &Path, value: &T) -> Result<()> {
+    let mut data = Vec::new();
+    value.serialize(&mut data)?;
+    write_file(path, &data)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {:?}", path))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("failed to create {:?}", path))?;
+    serde_json::to_writer(BufWriter::new(file), value)?;
+    Ok(())
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse {:?}", path))
+}
+
+/// Extract the body from a signed beacon block
+fn block_body(block: SignedBeaconBlock) -> BeaconBlockBody {
+    match block {
+        SignedBeaconBlock::Phase0(block) => BeaconBlockBody::Phase0(block.message.body),
+        SignedBeaconBlock::Altair(block) => BeaconBlockBody::Altair(block.message.body),
+        SignedBeaconBlock::Bellatrix(block) => BeaconBlockBody::Bellatrix(block.message.body),
+        SignedBeaconBlock::Capella(block) => BeaconBlockBody::Capella(block.message.body),
+        SignedBeaconBlock::Deneb(block) => BeaconBlockBody::Deneb(block.message.body),
+        SignedBeaconBlock::Electra(block) => BeaconBlockBody::Electra(block.message.body),
+    }
+}
+
+/// Build the signed header of a signed beacon block
+fn block_header(block: &SignedBeaconBlock) -> Result<SignedBeaconBlockHeader> {
+    macro_rules! header {
+        ($block:expr) => {
+            SignedBeaconBlockHeader {
+                message: BeaconBlockHeader {
+                    slot: $block.message.slot,
+                    proposer_index: $block.message.proposer_index,
+                    parent_root: $block.message.parent_root,
+                    state_root: $block.message.state_root,
+                    body_root: $block.message.body.hash_tree_root()?,
+                },
+                signature: $block.signature.clone(),
+            }
+        };
+    }
+    Ok(match block {
+        SignedBeaconBlock::Phase0(block) => header!(block),
+        SignedBeaconBlock::Altair(block) => header!(block),
+        SignedBeaconBlock::Bellatrix(block) => header!(block),
+        SignedBeaconBlock::Capella(block) => header!(block),
+        SignedBeaconBlock::Deneb(block) => header!(block),
+        SignedBeaconBlock::Electra(block) => header!(block),
+    })
+}
+
+/// Fetch the withdrawal vault account proof at the execution block of a block body and check it
+/// against the execution state root
+async fn fetch_withdrawal_vault_proof(
+    execution_rpc_url: Url,
+    network: &NetworkConfig,
+    body: &BeaconBlockBody,
+) -> Result<AccountProof> {
+    let execution =
+        execution_payload(body).context("beacon block does not contain an execution payload")?;
+    tracing::info!(
+        "Fetching withdrawal vault proof at execution block {}",
+        execution.block_number
+    );
+    let proof = fetch_account_proof(
+        execution_rpc_url,
+        network.withdrawal_vault,
+        execution.block_number,
+    )
+    .await?;
+    proof.verify(execution.state_root)?;
+    Ok(proof)
+}
+
+/// Fetch the `eth_getProof` account proof for an address at the given execution block
+async fn fetch_account_proof(
+    execution_rpc_url: Url,
+    address: Address,
+    block_number: u64,
+) -> Result<AccountProof> {
+    let provider = ProviderBuilder::new().on_http(execution_rpc_url);
+    let proof = provider
+        .get_proof(address, vec![])
+        .number(block_number)
+        .await?;
+
+    Ok(AccountProof {
+        address: proof.address,
+        balance: proof.balance,
+        nonce: proof.nonce,
+        storage_root: proof.storage_hash,
+        code_hash: proof.code_hash,
+        proof: proof.account_proof,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use ethereum_consensus::{electra, primitives::Root};
+
+    use super::*;
+
+    /// First slot of the Electra fork on mainnet
+    const SLOT: u64 = 364032 * 32;
+
+    fn electra_state() -> BeaconState {
+        BeaconState::Electra(electra::mainnet::BeaconState {
+            slot: SLOT,
+            ..Default::default()
+        })
+    }
+
+    fn electra_block(state_root: Root) -> SignedBeaconBlock {
+        let mut block = electra::mainnet::SignedBeaconBlock::default();
+        block.message.slot = SLOT;
+        block.message.state_root = state_root;
+        SignedBeaconBlock::Electra(block)
+    }
+
+    fn write_snappy<T: ssz_rs::Serialize>(path: &Path, value: &T) {
+        let mut data = Vec::new();
+        value.serialize(&mut data).unwrap();
+        let mut encoder = snap::write::FrameEncoder::new(File::create(path).unwrap());
+        encoder.write_all(&data).unwrap();
+    }
+
+    /// Write a raw SSZ state and a snappy framed block for it, returning their paths
+    fn write_files(
+        dir: &Path,
+        state: &BeaconState,
+        block: &SignedBeaconBlock,
+    ) -> (PathBuf, PathBuf) {
+        let (state_path, block_path) = (dir.join("state.ssz"), dir.join("block.ssz_snappy"));
+        write_ssz(&state_path, state).unwrap();
+        write_snappy(&block_path, block);
+        (state_path, block_path)
+    }
+
+    #[test]
+    fn loads_raw_and_snappy_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = electra_state();
+        let state_root = state.hash_tree_root().unwrap();
+        let block = electra_block(state_root);
+        let (state_path, block_path) = write_files(dir.path(), &state, &block);
+
+        let (header, loaded_block, loaded_state) =
+            load_ssz_files(&state_path, &block_path, None, &NetworkConfig::mainnet()).unwrap();
+        assert_eq!(header.message, block_header(&block).unwrap().message);
+        assert_eq!(header.message.slot, SLOT);
+        assert!(matches!(loaded_block, SignedBeaconBlock::Electra(_)));
+        assert_eq!(loaded_state.hash_tree_root().unwrap(), state_root);
+    }
+
+    #[test]
+    fn header_file_must_match_the_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = electra_state();
+        let block = electra_block(state.hash_tree_root().unwrap());
+        let (state_path, block_path) = write_files(dir.path(), &state, &block);
+
+        let header_path = dir.path().join("header.ssz");
+        let mut header = block_header(&block).unwrap();
+        write_ssz(&header_path, &header).unwrap();
+        let network = NetworkConfig::mainnet();
+        assert!(load_ssz_files(&state_path, &block_path, Some(&header_path), &network).is_ok());
+
+        header.message.proposer_index += 1;
+        write_ssz(&header_path, &header).unwrap();
+        assert!(load_ssz_files(&state_path, &block_path, Some(&header_path), &network).is_err());
+    }
+
+    #[tokio::test]
+    async fn state_must_match_the_block_state_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = electra_state();
+        let block = electra_block(Root::default());
+        let (state_path, block_path) = write_files(dir.path(), &state, &block);
+
+        // this fails before the execution node is contacted
+        let err = Fetched::from_ssz_files(
+            &state_path,
+            &block_path,
+            None,
+            "http://127.0.0.1:9".parse().unwrap(),
+            &NetworkConfig::mainnet(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("state root"), "{}", err);
+    }
+}
@@ -0,0 +1,387 @@
+//! Building the manifest and chunk preimages the coprocessor loads through the preimage oracle
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+};
+
+use alloy::{hex, primitives::B256};
+use anyhow::{bail, Context, Result};
+use ethereum_consensus::{
+    phase0::SignedBeaconBlockHeader,
+    types::mainnet::{BeaconBlockBody, BeaconState},
+};
+use fastcdc::v2020::FastCDC;
+use io::{
+    build_multiproof, field_roots, AccountProof, Chunk, ChunkIndex, Compression, Encoding,
+    Manifest, NetworkConfig, PartialBeaconState, ReportFields, Section, SectionKind,
+    StakingModules, StateEncoding, StateFormat,
+};
+use sha3::{Digest, Keccak256};
+use ssz_rs::prelude::*;
+
+use crate::operator_client::Preimage;
+
+// Bounds for content defined chunking. Preimages are at most MAX_CHUNK_SIZE before compression
+const MIN_CHUNK_SIZE: u32 = 1024 * 32;
+const AVG_CHUNK_SIZE: u32 = 1024 * 128;
+const MAX_CHUNK_SIZE: u32 = 1024 * 256;
+const KECCACK_HASH_TYPE: u8 = 2;
+
+const MANIFEST_FILE: &str = "manifest.cbor";
+const PREIMAGES_DIR: &str = "preimages";
+/// Root of the block the inputs were built from, hex encoded
+const BLOCK_ROOT_FILE: &str = "block_root";
+
+/// The manifest and the chunks of every section it lists
+#[derive(Debug)]
+pub struct Inputs {
+    /// Root of the block header in the block header section, which the report is requested with
+    pub block_root: B256,
+    pub manifest: Manifest,
+    /// Chunk and chunk index node preimages by hash. Identical chunks are only stored once
+    preimages: HashMap<[u8; 32], Vec<u8>>,
+}
+
+impl Inputs {
+    fn new(block_root: B256, network: String, state_encoding: StateEncoding) -> Self {
+        Self {
+            block_root,
+            manifest: Manifest::new(MAX_CHUNK_SIZE as u64, network, state_encoding),
+            preimages: HashMap::new(),
+        }
+    }
+
+    /// Split the data into content defined chunks, compress each one and record them as a section
+    /// of the manifest.
+    /// Chunk boundaries depend only on the nearby data so inserting or changing bytes (e.g. new
+    /// validators or balance updates) only changes the chunks around the edit
+    fn add_section(
+        &mut self,
+        kind: SectionKind,
+        encoding: Encoding,
+        compression: Compression,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut chunks = Vec::new();
+        for chunk in FastCDC::new(data, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE) {
+            let preimage = compress(
+                compression,
+                &data[chunk.offset..chunk.offset + chunk.length],
+            )?;
+            let hash = keccak(&preimage);
+            self.preimages.insert(hash, preimage);
+            chunks.push(Chunk {
+                hash,
+                length: chunk.length as u64,
+            });
+        }
+        // large sections list their chunks in index nodes loaded separately from the manifest
+        let (index, nodes) = ChunkIndex::build(chunks)?;
+        for node in nodes {
+            self.preimages.insert(keccak(&node), node);
+        }
+        self.manifest.add_section(Section {
+            kind,
+            encoding,
+            compression,
+            length: data.len() as u64,
+            index,
+        });
+        Ok(())
+    }
+
+    pub fn get_manifest_hash(&self) -> [u8; 32] {
+        keccak(&self.manifest.to_bytes().unwrap())
+    }
+
+    /// The manifest followed by the index nodes and chunks of each section in manifest order, as
+    /// uploaded to the operator. The order only depends on the manifest so upload batches are
+    /// the same on every run
+    pub fn preimages(&self) -> Result<Vec<Preimage>> {
+        let mut preimages = vec![(
+            KECCACK_HASH_TYPE,
+            self.get_manifest_hash().to_vec(),
+            self.manifest.to_bytes()?,
+        )];
+        let mut seen = HashSet::new();
+        for section in &self.manifest.sections {
+            self.push_index(&section.index, &mut seen, &mut preimages)?;
+        }
+        Ok(preimages)
+    }
+
+    /// Push the preimages under an index node in the order the coprocessor walks them,
+    /// skipping any already pushed
+    fn push_index(
+        &self,
+        index: &ChunkIndex,
+        seen: &mut HashSet<[u8; 32]>,
+        preimages: &mut Vec<Preimage>,
+    ) -> Result<()> {
+        let hashes: Vec<[u8; 32]> = match index {
+            ChunkIndex::Chunks(chunks) => chunks.iter().map(|c| c.hash).collect(),
+            ChunkIndex::Nodes(refs) => refs.iter().map(|r| r.hash).collect(),
+        };
+        for hash in hashes {
+            // identical nodes list identical chunks so a node already pushed has nothing new
+            if !seen.insert(hash) {
+                continue;
+            }
+            let preimage = self
+                .preimages
+                .get(&hash)
+                .with_context(|| format!("missing preimage {}", hex::encode(hash)))?;
+            preimages.push((KECCACK_HASH_TYPE, hash.to_vec(), preimage.clone()));
+            if let ChunkIndex::Nodes(_) = index {
+                self.push_index(&ChunkIndex::from_bytes(preimage)?, seen, preimages)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the manifest, the block root and each preimage, named by its hex encoded hash, to a
+    /// directory
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let preimages_dir = dir.join(PREIMAGES_DIR);
+        // clear out chunks from an earlier build so the directory holds exactly these inputs
+        if preimages_dir.exists() {
+            std::fs::remove_dir_all(&preimages_dir)?;
+        }
+        std::fs::create_dir_all(&preimages_dir)
+            .with_context(|| format!("failed to create directory {:?}", preimages_dir))?;
+        for (hash, preimage) in &self.preimages {
+            std::fs::write(preimages_dir.join(hex::encode(hash)), preimage)?;
+        }
+        std::fs::write(dir.join(MANIFEST_FILE), self.manifest.to_bytes()?)?;
+        std::fs::write(dir.join(BLOCK_ROOT_FILE), self.block_root.to_string())?;
+        Ok(())
+    }
+
+    /// Load inputs written by [Inputs::save], checking each preimage matches its name
+    pub fn load(dir: &Path) -> Result<Self> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest = Manifest::from_bytes(
+            &std::fs::read(&manifest_path)
+                .with_context(|| format!("failed to read {:?}", manifest_path))?,
+        )?;
+        let block_root_path = dir.join(BLOCK_ROOT_FILE);
+        let block_root = B256::from_str(
+            std::fs::read_to_string(&block_root_path)
+                .with_context(|| format!("failed to read {:?}", block_root_path))?
+                .trim(),
+        )
+        .with_context(|| format!("invalid block root in {:?}", block_root_path))?;
+        let mut preimages = HashMap::new();
+        for entry in std::fs::read_dir(dir.join(PREIMAGES_DIR))? {
+            let path = entry?.path();
+            let preimage = std::fs::read(&path)?;
+            let hash = keccak(&preimage);
+            if path.file_name().and_then(|name| name.to_str()) != Some(&hex::encode(hash)) {
+                bail!("Preimage {:?} does not match its hash", path);
+            }
+            preimages.insert(hash, preimage);
+        }
+        Ok(Self {
+            block_root,
+            manifest,
+            preimages,
+        })
+    }
+}
+
+pub fn build_inputs(
+    network: NetworkConfig,
+    state_format: StateFormat,
+    compression: Compression,
+    staking_modules: Option<StakingModules>,
+    withdrawal_vault_proof: AccountProof,
+    beacon_block: SignedBeaconBlockHeader,
+    beacon_block_body: BeaconBlockBody,
+    beacon_state: BeaconState,
+) -> Result<Inputs> {
+    let mut block_data = Vec::new();
+    beacon_block.serialize(&mut block_data)?;
+    let mut block_body_data = Vec::new();
+    beacon_block_body.serialize(&mut block_body_data)?;
+    let mut beacon_state_data = Vec::new();
+    let state_encoding = match state_format {
+        StateFormat::Full => {
+            beacon_state.serialize(&mut beacon_state_data)?;
+            StateEncoding::Full
+        }
+        StateFormat::Partial => {
+            PartialBeaconState::from_state(&beacon_state)?.serialize(&mut beacon_state_data)?;
+            StateEncoding::Partial
+        }
+        StateFormat::Multiproof => {
+            let field_roots = field_roots(&beacon_state)?;
+            ReportFields::from_state(&beacon_state)?.serialize(&mut beacon_state_data)?;
+            StateEncoding::Multiproof {
+                field_count: field_roots.len(),
+                helpers: build_multiproof(&field_roots),
+            }
+        }
+    };
+
+    // the block root is the root of the header message, as given by EIP-4788 and the beacon API
+    let block_root = B256::from_slice(&beacon_block.message.hash_tree_root()?.to_vec());
+    let mut inputs = Inputs::new(block_root, network.name, state_encoding);
+    // only the state chunks are large enough for compression to be worthwhile
+    inputs.add_section(
+        SectionKind::BlockHeader,
+        Encoding::Ssz,
+        Compression::None,
+        &block_data,
+    )?;
+    inputs.add_section(
+        SectionKind::BlockBody,
+        Encoding::Ssz,
+        Compression::None,
+        &block_body_data,
+    )?;
+    inputs.add_section(
+        SectionKind::State,
+        Encoding::Ssz,
+        compression,
+        &beacon_state_data,
+    )?;
+    if let Some(staking_modules) = staking_modules {
+        inputs.add_section(
+            SectionKind::StakingModules,
+            Encoding::Cbor,
+            Compression::None,
+            &staking_modules.to_bytes()?,
+        )?;
+    }
+    inputs.add_section(
+        SectionKind::WithdrawalVaultProof,
+        Encoding::Cbor,
+        Compression::None,
+        &withdrawal_vault_proof.to_bytes()?,
+    )?;
+
+    // decode the manifest the same way the coprocessor will so a layout mismatch fails here
+    // rather than after the onchain transaction
+    Manifest::from_bytes(&inputs.manifest.to_bytes()?)
+        .context("built a manifest the coprocessor would reject")?;
+    tracing::info!(
+        "Built manifest version {} with {} sections totalling {} bytes",
+        inputs.manifest.version,
+        inputs.manifest.sections.len(),
+        inputs.manifest.total_length
+    );
+
+    Ok(inputs)
+}
+
+/// Compress a chunk. zstd uses its default level
+fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match compression {
+        Compression::None => data.to_vec(),
+        Compression::Zstd => zstd::encode_all(data, 0)?,
+        Compression::Snappy => snap::raw::Encoder::new().compress_vec(data)?,
+    })
+}
+
+fn keccak(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(data);
+    let result = hasher.finalize();
+    result.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (x >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn inputs(state: &[u8]) -> Inputs {
+        let mut inputs = Inputs::new(
+            B256::repeat_byte(1),
+            "mainnet".to_string(),
+            StateEncoding::Full,
+        );
+        inputs
+            .add_section(
+                SectionKind::BlockHeader,
+                Encoding::Ssz,
+                Compression::None,
+                &data(1, 1000),
+            )
+            .unwrap();
+        inputs
+            .add_section(SectionKind::State, Encoding::Ssz, Compression::Zstd, state)
+            .unwrap();
+        inputs
+    }
+
+    #[test]
+    fn preimages_are_in_manifest_order() {
+        // the state repeats itself so some of its chunks are identical
+        let mut state = data(2, 2 * 1024 * 1024);
+        state.extend_from_within(..);
+        let inputs = inputs(&state);
+        let preimages = inputs.preimages().unwrap();
+
+        assert_eq!(preimages[0].1, inputs.get_manifest_hash().to_vec());
+        let expected: Vec<[u8; 32]> = inputs
+            .manifest
+            .sections
+            .iter()
+            .flat_map(|section| match &section.index {
+                ChunkIndex::Chunks(chunks) => chunks.iter().map(|c| c.hash).collect::<Vec<_>>(),
+                ChunkIndex::Nodes(_) => unreachable!("too few chunks for index nodes"),
+            })
+            .fold(Vec::new(), |mut hashes, hash| {
+                if !hashes.contains(&hash) {
+                    hashes.push(hash);
+                }
+                hashes
+            });
+        let hashes: Vec<Vec<u8>> = preimages[1..].iter().map(|p| p.1.clone()).collect();
+        assert_eq!(
+            hashes,
+            expected.iter().map(|h| h.to_vec()).collect::<Vec<_>>()
+        );
+        assert_eq!(hashes.len(), inputs.preimages.len());
+    }
+
+    #[test]
+    fn chunks_decompress_in_the_machine() {
+        let data = data(4, 4096).repeat(64);
+        for compression in [Compression::None, Compression::Zstd, Compression::Snappy] {
+            let compressed = compress(compression, &data).unwrap();
+            assert_eq!(
+                compression
+                    .decompress(compressed, data.len() as u64)
+                    .unwrap(),
+                data,
+                "{}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_inputs() {
+        let inputs = inputs(&data(3, 1024 * 1024));
+        let dir = tempfile::tempdir().unwrap();
+        inputs.save(dir.path()).unwrap();
+        let loaded = Inputs::load(dir.path()).unwrap();
+        assert_eq!(loaded.block_root, inputs.block_root);
+        assert_eq!(loaded.preimages().unwrap(), inputs.preimages().unwrap());
+    }
+}
@@ -4,30 +4,47 @@
 //! - Retrieving the beacon chain data via RPC and building inputs
 //! - Uploading preimage data to operators
 //! - Calling the contract to initiate the coprocessor execution
+//!
+//! Each of these stages can be run on its own with the data passed between them stored in a
+//! directory per slot, so a stage that fails can be rerun without repeating the earlier ones.
 
+mod beacon_cache;
 mod beacon_client;
+mod beacon_events;
+mod beacon_roots;
+mod daemon;
+mod fetch;
+mod inputs;
+mod operator_client;
+mod ssz_files;
+mod upload;
 
-use std::str::FromStr;
+use std::{path::PathBuf, pin::pin, str::FromStr, time::Duration};
 
 use alloy::{
-    network::EthereumWallet,
+    network::{Ethereum, EthereumWallet},
     primitives::{Address, B256, U256},
-    providers::ProviderBuilder,
+    providers::{PendingTransactionBuilder, ProviderBuilder},
     signers::local::PrivateKeySigner,
 };
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use beacon_cache::{root_hex, CacheArgs};
 use beacon_client::BeaconClient;
-use clap::Parser;
-use ethereum_consensus::{phase0::SignedBeaconBlockHeader, types::mainnet::BeaconState};
-use io::derive_report;
-use sha3::{Digest, Keccak256};
-use ssz_rs::prelude::*;
+use beacon_events::{BeaconEvent, EventTopic};
+use beacon_roots::find_block_root;
+use clap::{Parser, Subcommand};
+use daemon::{DaemonState, FrameArgs, FrameConfig, FrameStatus};
+use fetch::Fetched;
+use futures::{Stream, StreamExt};
+use inputs::{build_inputs, Inputs};
+use io::{
+    derive_report, Compression, Network, NetworkConfig, OracleReport, StakingModules, StateFormat,
+};
+use operator_client::{EnsureStatus, OperatorClient};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use upload::{upload_preimages, UploadOptions};
 use url::Url;
 
-const CHUNK_SIZE: usize = 1024 * 256;
-const KECCACK_HASH_TYPE: u8 = 2;
-
 alloy::sol!(
     #[sol(rpc)]
     CartesiLidoOracle,
@@ -35,15 +52,198 @@ alloy::sol!(
 );
 
 #[derive(Parser, Debug)]
-struct Args {
-    /// Ethereum beacon node HTTP RPC endpoint.
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Fetch the beacon chain data for a slot, upload the inputs and request a report onchain
+    Run(RunArgs),
+    /// Follow Lido's reporting frames and request a report for each one once it is finalized
+    Daemon(DaemonArgs),
+    /// Download the beacon block, state and withdrawal vault proof for a slot to disk
+    Fetch(FetchArgs),
+    /// Build the manifest and chunks from fetched data and write them to disk
+    BuildInputs(BuildInputsArgs),
+    /// Upload built inputs to the operator
+    Upload(UploadArgs),
+    /// Request a report onchain for uploaded inputs
+    Submit(SubmitArgs),
+    /// Compute the report from fetched data locally and print it
+    Report(ReportArgs),
+    /// Ask the solver to make a published machine available to operators and wait until it is ready
+    Ensure(EnsureArgs),
+    /// Manage the cache of beacon blocks and states
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Evict the least recently used entries until the cache is within its maximum size
+    Prune(PruneArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct PruneArgs {
+    #[clap(flatten)]
+    cache: CacheArgs,
+
+    /// Remove every entry
+    #[clap(long)]
+    all: bool,
+}
+
+/// Where the data for a slot is stored between stages
+#[derive(clap::Args, Debug)]
+struct SlotArgs {
+    /// Beacon slot to generate oracle report for
+    #[clap(long)]
+    slot: u64,
+
+    /// Directory to store fetched data and built inputs in, under a subdirectory per slot
+    #[clap(long, env, default_value = "./oracle-data")]
+    data_dir: PathBuf,
+}
+
+impl SlotArgs {
+    fn fetched_dir(&self) -> PathBuf {
+        self.data_dir.join(self.slot.to_string()).join("fetched")
+    }
+
+    fn inputs_dir(&self) -> PathBuf {
+        self.data_dir.join(self.slot.to_string()).join("inputs")
+    }
+}
+
+/// Local SSZ files to use instead of fetched data, e.g. from a checkpoint sync dump
+#[derive(clap::Args, Debug)]
+struct SszFileArgs {
+    /// SSZ encoded beacon state, optionally snappy framed
+    #[clap(long, requires_all(["block_file", "execution_rpc_url"]))]
+    state_file: Option<PathBuf>,
+
+    /// SSZ encoded signed beacon block for the same slot as the state, optionally snappy framed
+    #[clap(long, requires("state_file"))]
+    block_file: Option<PathBuf>,
+
+    /// SSZ encoded signed beacon block header, optionally snappy framed.
+    /// If not provided the header is derived from the block
+    #[clap(long, requires("state_file"))]
+    header_file: Option<PathBuf>,
+
+    /// Execution node endpoint to prove the withdrawal vault balance with when loading from SSZ
+    /// files. Data stored by the fetch command already includes the proof
+    #[clap(long, env)]
+    execution_rpc_url: Option<Url>,
+}
+
+#[derive(clap::Args, Debug)]
+struct NetworkArgs {
+    /// Network to generate the report for (mainnet, holesky, sepolia, hoodi or custom)
+    #[clap(long, env, default_value = "mainnet")]
+    network: Network,
+
+    /// Path to a JSON network config file. Required for a custom network
+    #[clap(long, env, required_if_eq("network", "custom"))]
+    network_config: Option<PathBuf>,
+
+    /// Path to a JSON file assigning validator pubkeys to Lido staking modules.
+    /// The mapping is committed to by the manifest and the coprocessor emits the per-module counts
+    /// as a report alongside the notice. If not provided no per-module counts are derived
+    #[clap(long, env)]
+    staking_modules: Option<PathBuf>,
+}
+
+impl NetworkArgs {
+    /// Resolve the network config selected by the `--network` and `--network-config` args
+    fn network_config(&self) -> Result<NetworkConfig> {
+        match &self.network_config {
+            Some(path) => {
+                let file = std::fs::File::open(path)
+                    .with_context(|| format!("failed to open network config {:?}", path))?;
+                Ok(serde_json::from_reader(file)?)
+            }
+            None => self
+                .network
+                .config()
+                .context("a network config file is required for a custom network"),
+        }
+    }
+
+    /// Load the staking module mapping if one was provided
+    fn staking_modules(&self) -> Result<Option<StakingModules>> {
+        self.staking_modules
+            .as_ref()
+            .map(|path| {
+                let file = std::fs::File::open(path)
+                    .with_context(|| format!("failed to open staking modules {:?}", path))?;
+                let modules: StakingModules = serde_json::from_reader(file)?;
+                modules.validate()?;
+                Ok(modules)
+            })
+            .transpose()
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    /// Ethereum beacon node HTTP RPC endpoints, separated by commas.
+    /// Requests fail over to the next endpoint if one fails
+    #[clap(long, env, value_delimiter = ',', required = true)]
+    beacon_rpc_url: Vec<Url>,
+
+    /// Fetch the block header from every beacon endpoint and require at least this many to respond,
+    /// refusing to proceed if any disagree on its root. Must be between 1 and the number of endpoints
+    #[clap(long, env)]
+    beacon_quorum: Option<usize>,
+
+    /// Execution node endpoint for the network the beacon chain belongs to.
+    /// Used to prove the withdrawal vault balance, which the coprocessor requires.
+    /// This is separate from the eth-rpc-url as the contract may be deployed to a devnet
     #[clap(long, env)]
-    beacon_rpc_url: Url,
+    execution_rpc_url: Url,
+
+    #[clap(flatten)]
+    cache: CacheArgs,
+}
+
+impl SourceArgs {
+    /// A caching client for the beacon endpoints
+    fn beacon_client(&self) -> Result<BeaconClient> {
+        Ok(BeaconClient::new(self.beacon_rpc_url.clone())?
+            .with_cache(self.cache.cache())
+            .with_header_quorum(self.beacon_quorum)?)
+    }
+}
 
+#[derive(clap::Args, Debug)]
+struct BuildArgs {
+    /// Format to load the beacon state into the coprocessor with (full, partial or multiproof).
+    /// The partial format replaces fields not needed for the report with their roots.
+    /// The multiproof format ships only the report fields with a multiproof against the state root
+    #[clap(long, env, default_value = "partial")]
+    state_format: StateFormat,
+
+    /// Compression to apply to each beacon state chunk (none, zstd or snappy)
+    #[clap(long, env, default_value = "zstd")]
+    compression: Compression,
+}
+
+#[derive(clap::Args, Debug)]
+struct OperatorArgs {
     /// Coprocessor operator url
     #[clap(long, env)]
     operator_url: Url,
 
+    #[clap(flatten)]
+    upload: UploadOptions,
+}
+
+#[derive(clap::Args, Debug)]
+struct ContractArgs {
     /// Ethereum Node endpoint.
     #[clap(long, env)]
     eth_rpc_url: Url,
@@ -56,11 +256,141 @@ struct Args {
     #[clap(long, env)]
     contract_address: Address,
 
+    /// Request the report with `generateReport`, which has the contract look up the block root
+    /// from the EIP-4788 beacon roots contract rather than trusting the one given. The root is
+    /// checked against the inputs before submitting. Requires the contract to be deployed to the
+    /// same chain as the beacon node
+    #[clap(long, env)]
+    trusted: bool,
+}
+
+/// Args for the commands that run every stage in turn
+#[derive(clap::Args, Debug)]
+struct PipelineArgs {
+    #[clap(flatten)]
+    source: SourceArgs,
+
+    #[clap(flatten)]
+    network: NetworkArgs,
+
+    #[clap(flatten)]
+    build: BuildArgs,
+
+    #[clap(flatten)]
+    operator: OperatorArgs,
+
+    #[clap(flatten)]
+    contract: ContractArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    #[clap(flatten)]
+    pipeline: PipelineArgs,
+
     /// Beacon slot to generate oracle report for
     #[clap(long)]
     slot: u64,
 }
 
+#[derive(clap::Args, Debug)]
+struct DaemonArgs {
+    #[clap(flatten)]
+    pipeline: PipelineArgs,
+
+    #[clap(flatten)]
+    frames: FrameArgs,
+
+    /// File recording the frames that have been submitted
+    #[clap(long, env, default_value = "./oracle-data/daemon-state.json")]
+    state_file: PathBuf,
+
+    /// Seconds to wait for a finalized checkpoint event before checking for a newly finalized
+    /// frame anyway, in case events were missed
+    #[clap(long, env, default_value_t = 60)]
+    poll_interval: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct FetchArgs {
+    #[clap(flatten)]
+    slot: SlotArgs,
+
+    #[clap(flatten)]
+    source: SourceArgs,
+
+    #[clap(flatten)]
+    network: NetworkArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct BuildInputsArgs {
+    #[clap(flatten)]
+    slot: SlotArgs,
+
+    #[clap(flatten)]
+    files: SszFileArgs,
+
+    #[clap(flatten)]
+    network: NetworkArgs,
+
+    #[clap(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct UploadArgs {
+    #[clap(flatten)]
+    slot: SlotArgs,
+
+    #[clap(flatten)]
+    operator: OperatorArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct SubmitArgs {
+    #[clap(flatten)]
+    slot: SlotArgs,
+
+    #[clap(flatten)]
+    contract: ContractArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+    #[clap(flatten)]
+    slot: SlotArgs,
+
+    #[clap(flatten)]
+    files: SszFileArgs,
+
+    #[clap(flatten)]
+    network: NetworkArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct EnsureArgs {
+    /// Coprocessor solver url
+    #[clap(long, env)]
+    solver_url: Url,
+
+    /// IPFS CID of the machine CAR file
+    #[clap(long)]
+    cid: String,
+
+    /// Hex encoded hash of the machine
+    #[clap(long)]
+    machine_hash: String,
+
+    /// Size in bytes of the machine CAR file
+    #[clap(long)]
+    size: u64,
+
+    /// Seconds to wait between checks
+    #[clap(long, default_value_t = 3)]
+    interval: u64,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::registry()
@@ -68,164 +398,420 @@ async fn main() -> Result<()> {
         .with(EnvFilter::from_default_env())
         .init();
 
-    let Args {
-        beacon_rpc_url,
+    match Cli::parse().command {
+        Command::Run(args) => run(args).await,
+        Command::Daemon(args) => daemon(args).await,
+        Command::Fetch(args) => fetch(args).await,
+        Command::BuildInputs(args) => build(args).await,
+        Command::Upload(args) => upload(args).await,
+        Command::Submit(args) => submit(args).await,
+        Command::Report(args) => report(args).await,
+        Command::Ensure(args) => ensure(args).await,
+        Command::Cache(CacheCommand::Prune(args)) => prune_cache(args),
+    }
+}
+
+/// Run every stage in turn without storing the intermediate data
+async fn run(args: RunArgs) -> Result<()> {
+    let (block_root, manifest_hash) = prepare(&args.pipeline, args.slot).await?;
+    let tx_hash = send_report(
+        &args.pipeline.contract,
+        args.slot,
+        block_root,
+        manifest_hash,
+    )
+    .await?
+    .watch()
+    .await?;
+    tracing::info!("Report generation initiated with tx hash: {:?}", tx_hash);
+    Ok(())
+}
+
+/// Run every stage in turn for the reference slot of each new frame
+async fn daemon(args: DaemonArgs) -> Result<()> {
+    let network = args.pipeline.network.network_config()?;
+    let frames = FrameConfig::resolve(
+        &args.frames,
+        Some(args.pipeline.source.execution_rpc_url.clone()),
+        network.slots_per_epoch,
+    )
+    .await?;
+    tracing::info!("Following reporting frames: {:?}", frames);
+    let mut state = DaemonState::load(&args.state_file)?;
+    // finality changes with every epoch so must not be read through the cache
+    let beacon_client = BeaconClient::new(args.pipeline.source.beacon_rpc_url.clone())?;
+    // head events arrive every slot which keeps the subscription from looking stalled
+    let mut events = pin!(beacon_client.events(&[
+        EventTopic::Head,
+        EventTopic::FinalizedCheckpoint,
+        EventTopic::ChainReorg,
+    ]));
+
+    loop {
+        if let Err(e) = report_latest_frame(&args, &frames, &beacon_client, &mut state).await {
+            tracing::error!("Failed to report frame: {:#}", e);
+            for (endpoint, health) in beacon_client.health() {
+                tracing::info!(
+                    "Beacon endpoint {}: {} ok, {} failed, last error: {:?}",
+                    endpoint,
+                    health.successes,
+                    health.failures,
+                    health.last_error
+                );
+            }
+        }
+        wait_for_finality(&mut events, Duration::from_secs(args.poll_interval)).await;
+    }
+}
+
+/// Wait until a new checkpoint is finalized or the timeout passes
+async fn wait_for_finality(
+    events: &mut (impl Stream<Item = Result<BeaconEvent, beacon_client::Error>> + Unpin),
+    timeout: Duration,
+) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(event) = tokio::time::timeout_at(deadline, events.next()).await {
+        match event {
+            Some(Ok(BeaconEvent::FinalizedCheckpoint(checkpoint))) => {
+                tracing::info!(
+                    "Finalized epoch {} at block {} state {} (optimistic: {})",
+                    checkpoint.epoch,
+                    root_hex(&checkpoint.block),
+                    root_hex(&checkpoint.state),
+                    checkpoint.execution_optimistic
+                );
+                return;
+            }
+            Some(Ok(BeaconEvent::Head(head))) => tracing::debug!(
+                "Head at slot {} block {} state {} (epoch transition: {}, optimistic: {})",
+                head.slot,
+                root_hex(&head.block),
+                root_hex(&head.state),
+                head.epoch_transition,
+                head.execution_optimistic
+            ),
+            Some(Ok(BeaconEvent::ChainReorg(reorg))) => tracing::warn!(
+                "Chain reorg of depth {} at slot {} in epoch {} from {} to {} (optimistic: {})",
+                reorg.depth,
+                reorg.slot,
+                reorg.epoch,
+                root_hex(&reorg.old_head_block),
+                root_hex(&reorg.new_head_block),
+                reorg.execution_optimistic
+            ),
+            Some(Err(e)) => tracing::warn!("Beacon event stream: {}", e),
+            // the stream reconnects rather than ending
+            None => return,
+        }
+    }
+}
+
+/// Request a report for the latest finalized frame unless it has been already.
+/// Earlier frames that were missed are skipped as only the latest report is of use
+async fn report_latest_frame(
+    args: &DaemonArgs,
+    frames: &FrameConfig,
+    beacon_client: &BeaconClient,
+    state: &mut DaemonState,
+) -> Result<()> {
+    let finalized = beacon_client
+        .get_finality_checkpoints("head")
+        .await?
+        .finalized
+        .epoch;
+    let Some(frame) = frames.latest_finalized_frame(finalized) else {
+        tracing::debug!("No frame finalized yet at epoch {}", finalized);
+        return Ok(());
+    };
+    let ref_slot = frames.ref_slot(frame);
+    match state.status(ref_slot) {
+        None => {}
+        Some(FrameStatus::Submitted { .. }) => {
+            tracing::debug!("Frame {} at ref slot {} already submitted", frame, ref_slot);
+            return Ok(());
+        }
+        Some(FrameStatus::Submitting) => {
+            tracing::warn!(
+                "Frame {} at ref slot {} may have been submitted before the daemon stopped. \
+                 Check the contract and remove it from {:?} to retry",
+                frame,
+                ref_slot,
+                args.state_file
+            );
+            return Ok(());
+        }
+    }
+
+    tracing::info!("Reporting frame {} at ref slot {}", frame, ref_slot);
+    let (block_root, manifest_hash) = prepare(&args.pipeline, ref_slot).await?;
+    state.set_status(ref_slot, FrameStatus::Submitting)?;
+    let pending =
+        match send_report(&args.pipeline.contract, ref_slot, block_root, manifest_hash).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                // the node did not accept the transaction so it is safe to retry
+                state.clear_status(ref_slot)?;
+                return Err(e);
+            }
+        };
+    state.set_status(
+        ref_slot,
+        FrameStatus::Submitted {
+            tx_hash: *pending.tx_hash(),
+        },
+    )?;
+    let tx_hash = pending.watch().await?;
+    tracing::info!(
+        "Report for frame {} initiated with tx hash: {:?}",
+        frame,
+        tx_hash
+    );
+    Ok(())
+}
+
+/// Fetch the data for a slot, then build and upload the inputs.
+/// Returns the block root and manifest hash to request the report with
+async fn prepare(args: &PipelineArgs, slot: u64) -> Result<(B256, B256)> {
+    let network = args.network.network_config()?;
+    let staking_modules = args.network.staking_modules()?;
+    tracing::info!("Using network config: {}", network.name);
+
+    let beacon_client = args.source.beacon_client()?;
+    let fetched = Fetched::fetch(
+        &beacon_client,
+        args.source.execution_rpc_url.clone(),
+        &network,
         slot,
-        operator_url,
-        eth_rpc_url,
-        eth_private_key,
-        contract_address,
-        ..
-    } = Args::parse();
-
-    let beacon_client = BeaconClient::new_with_cache(beacon_rpc_url, "./beacon-cache")?;
-    tracing::info!("Fetching beacon block for slot {}", slot);
-    let block_header = beacon_client.get_block_header(slot).await?;
-    tracing::info!("Fetching beacon state for slot {}", slot);
-    let beacon_state = beacon_client.get_beacon_state(slot).await?;
-
-    let block_root = block_header.hash_tree_root()?.to_vec();
-
-    let report = derive_report(&beacon_state);
+    )
+    .await?;
+
+    let report = compute_report(&fetched, &network, &staking_modules)?;
     tracing::info!("Report for this slot: {:?}", report);
 
-    tracing::info!("building inputs...");
-    let inputs = build_inputs::<CHUNK_SIZE>(block_header, beacon_state);
+    let inputs = build_fetched_inputs(fetched, network, staking_modules, &args.build)?;
 
-    // upload the chunks, block data and manifest to the operators to use in the preimage oracle
-    tracing::info!("Uploading to operator");
-    upload_to_operator(operator_url, &inputs).await?;
+    upload_inputs(&inputs, &args.operator).await?;
+    Ok((inputs.block_root, inputs.get_manifest_hash().into()))
+}
 
-    // call the contract to initiate the coprocessor execution
-    let signer = PrivateKeySigner::from_str(eth_private_key.as_str())?;
-    let wallet = EthereumWallet::from(signer);
-    let provider = ProviderBuilder::new().wallet(wallet).on_http(eth_rpc_url);
-    let contract = CartesiLidoOracle::new(contract_address, provider);
+async fn fetch(args: FetchArgs) -> Result<()> {
+    let network = args.network.network_config()?;
+    let beacon_client = args.source.beacon_client()?;
+    let fetched = Fetched::fetch(
+        &beacon_client,
+        args.source.execution_rpc_url,
+        &network,
+        args.slot.slot,
+    )
+    .await?;
 
-    tracing::info!("Initiating report with onchain transaction");
-    let tx_hash = contract
-        .generateReportUntrusted(
-            U256::from(slot),
-            B256::from_slice(&block_root),
-            inputs.get_manifest_hash().into(),
-        )
-        .send()
-        .await?
-        .watch()
-        .await?;
+    let dir = args.slot.fetched_dir();
+    fetched.save(&dir)?;
+    tracing::info!("Saved fetched data to {:?}", dir);
+    Ok(())
+}
 
-    tracing::info!("Report generation initiated with tx hash: {:?}", tx_hash);
+async fn build(args: BuildInputsArgs) -> Result<()> {
+    let network = args.network.network_config()?;
+    let staking_modules = args.network.staking_modules()?;
+    let fetched = load_fetched(&args.slot, &args.files, &network).await?;
+    let inputs = build_fetched_inputs(fetched, network, staking_modules, &args.build)?;
+
+    let dir = args.slot.inputs_dir();
+    inputs.save(&dir)?;
+    tracing::info!(
+        "Saved inputs with manifest hash 0x{} to {:?}",
+        alloy::hex::encode(inputs.get_manifest_hash()),
+        dir
+    );
+    Ok(())
+}
 
+async fn upload(args: UploadArgs) -> Result<()> {
+    let inputs = Inputs::load(&args.slot.inputs_dir())?;
+    upload_inputs(&inputs, &args.operator).await
+}
+
+async fn submit(args: SubmitArgs) -> Result<()> {
+    let inputs = Inputs::load(&args.slot.inputs_dir())?;
+    let tx_hash = send_report(
+        &args.contract,
+        args.slot.slot,
+        inputs.block_root,
+        inputs.get_manifest_hash().into(),
+    )
+    .await?
+    .watch()
+    .await?;
+    tracing::info!("Report generation initiated with tx hash: {:?}", tx_hash);
     Ok(())
 }
 
-#[derive(Debug)]
-struct Inputs {
-    manifest: io::Manifest,
-    block_data: Vec<u8>,
-    state_chunks: Vec<Vec<u8>>,
+async fn report(args: ReportArgs) -> Result<()> {
+    let network = args.network.network_config()?;
+    let staking_modules = args.network.staking_modules()?;
+    let fetched = load_fetched(&args.slot, &args.files, &network).await?;
+    let report = compute_report(&fetched, &network, &staking_modules)?;
+    println!("{:#?}", report);
+    println!("0x{}", alloy::hex::encode(report.abi_encode()));
+    Ok(())
 }
 
-impl Inputs {
-    fn get_manifest_hash(&self) -> [u8; 32] {
-        keccak(&self.manifest.to_bytes().unwrap())
+/// Poll the solver until the machine is ready
+async fn ensure(args: EnsureArgs) -> Result<()> {
+    let solver = OperatorClient::new(args.solver_url);
+    loop {
+        match solver
+            .ensure(&args.cid, &args.machine_hash, args.size)
+            .await?
+        {
+            EnsureStatus::Ready => {
+                tracing::info!("Machine {} is ready", args.machine_hash);
+                return Ok(());
+            }
+            EnsureStatus::Pending(status) => {
+                tracing::info!("Machine not ready yet: {}", status);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(args.interval)).await;
     }
 }
 
-fn build_inputs<const CHUNK_SIZE: usize>(
-    beacon_block: SignedBeaconBlockHeader,
-    beacon_state: BeaconState,
-) -> Inputs {
-    let mut block_data = Vec::new();
-    beacon_block.serialize(&mut block_data).unwrap();
-    let mut beacon_state_data = Vec::new();
-    beacon_state.serialize(&mut beacon_state_data).unwrap();
-    let state_chunks: Vec<_> = beacon_state_data
-        .chunks(CHUNK_SIZE)
-        .map(|c| c.to_vec())
-        .collect();
-
-    let manifest = io::Manifest {
-        block_hash: keccak(&block_data),
-        state_chunk_hashes: state_chunks.iter().map(|c| keccak(&c)).collect(),
+/// Load the data for a slot from local SSZ files if they were given, otherwise from the data
+/// stored by the fetch command
+async fn load_fetched(
+    slot: &SlotArgs,
+    files: &SszFileArgs,
+    network: &NetworkConfig,
+) -> Result<Fetched> {
+    let (Some(state_file), Some(block_file)) = (&files.state_file, &files.block_file) else {
+        return Fetched::load(&slot.fetched_dir());
     };
-
-    Inputs {
-        block_data,
-        state_chunks,
-        manifest,
+    let execution_rpc_url = files
+        .execution_rpc_url
+        .clone()
+        .context("an execution rpc url is required to prove the withdrawal vault balance")?;
+    let fetched = Fetched::from_ssz_files(
+        state_file,
+        block_file,
+        files.header_file.as_deref(),
+        execution_rpc_url,
+        network,
+    )
+    .await?;
+    if fetched.header.message.slot != slot.slot {
+        bail!(
+            "Files are for slot {} but slot {} was requested",
+            fetched.header.message.slot,
+            slot.slot
+        );
     }
+    Ok(fetched)
 }
 
-fn keccak(data: &[u8]) -> [u8; 32] {
-    let mut hasher = Keccak256::new();
-    hasher.update(data);
-    let result = hasher.finalize();
-    result.into()
-}
-
-async fn upload_to_operator(base_url: Url, inputs: &Inputs) -> Result<()> {
-    let client = reqwest::Client::new();
-
-    // upload all the preimages
-    let preimages: Vec<(u8, Vec<u8>, Vec<u8>)> = vec![
-        (
-            KECCACK_HASH_TYPE,
-            inputs.get_manifest_hash().to_vec(),
-            inputs.manifest.to_bytes()?,
-        ),
-        (
-            KECCACK_HASH_TYPE,
-            inputs.manifest.block_hash.to_vec(),
-            inputs.block_data.clone(),
-        ),
-    ]
-    .into_iter()
-    .chain(
-        inputs
-            .state_chunks
-            .iter()
-            .map(|c| (KECCACK_HASH_TYPE, keccak(c).to_vec(), c.clone())),
-    )
-    .collect();
+fn prune_cache(args: PruneArgs) -> Result<()> {
+    let max_size = if args.all {
+        0
+    } else {
+        args.cache.cache_max_mib * 1024 * 1024
+    };
+    let stats = args.cache.cache().prune(max_size)?;
+    tracing::info!(
+        "Removed {} cache entries freeing {} MiB",
+        stats.removed,
+        stats.freed / (1024 * 1024)
+    );
+    Ok(())
+}
 
-    // TODO: This should be batched but I am using an older version of the operator that doesn't support it
-    for preimage in preimages.clone() {
-        let res = client
-            .post(base_url.join("/upload_preimages/")?)
-            .body(serde_cbor::to_vec(&preimage)?)
-            .send()
-            .await?;
+/// Derive the report the coprocessor is expected to produce
+fn compute_report(
+    fetched: &Fetched,
+    network: &NetworkConfig,
+    staking_modules: &Option<StakingModules>,
+) -> Result<OracleReport> {
+    let mut report = derive_report(
+        &fetched.state,
+        network,
+        &staking_modules.clone().unwrap_or_default(),
+    )?;
+    report.report.withdrawalVaultBalanceWei = fetched.withdrawal_vault_proof.balance;
+    Ok(report)
+}
 
-        if res.status() != 200 {
-            let err = res.text().await?;
-            tracing::debug!("Preimage upload response: {:?}", err);
-            return Err(anyhow::anyhow!("Failed to upload preimages"));
-        }
-    }
+fn build_fetched_inputs(
+    fetched: Fetched,
+    network: NetworkConfig,
+    staking_modules: Option<StakingModules>,
+    args: &BuildArgs,
+) -> Result<Inputs> {
+    tracing::info!("building inputs...");
+    let block_body = fetched.block_body();
+    build_inputs(
+        network,
+        args.state_format,
+        args.compression,
+        staking_modules,
+        fetched.withdrawal_vault_proof,
+        fetched.header,
+        block_body,
+        fetched.state,
+    )
+}
 
-    // sanity check the preimages are uploaded correctly
-    let check_body: Vec<(u8, Vec<u8>)> = preimages
-        .into_iter()
-        .map(|(hash_type, hash, _)| (hash_type, hash))
-        .collect();
-
-    // TODO: Same here - batch once the operator supports it
-    for check in check_body {
-        let res = client
-            .post(base_url.join("/check_preimages_status/")?)
-            .body(serde_cbor::to_vec(&check)?)
-            .send()
-            .await?;
+/// Upload the chunks, block data and manifest to the operators to use in the preimage oracle
+async fn upload_inputs(inputs: &Inputs, args: &OperatorArgs) -> Result<()> {
+    tracing::info!("Uploading to operator");
+    let operator =
+        OperatorClient::new(args.operator_url.clone()).with_legacy(args.upload.legacy_upload);
+    upload_preimages(&operator, &inputs.preimages()?, &args.upload).await
+}
 
-        let status = res.status();
-        let text = res.text().await?;
+/// Call the contract to initiate the coprocessor execution.
+/// Returns once the transaction has been accepted by the node
+async fn send_report(
+    args: &ContractArgs,
+    slot: u64,
+    block_root: B256,
+    manifest_hash: B256,
+) -> Result<PendingTransactionBuilder<Ethereum>> {
+    let signer = PrivateKeySigner::from_str(args.eth_private_key.as_str())?;
+    let wallet = EthereumWallet::from(signer);
+    let provider = ProviderBuilder::new()
+        .wallet(wallet)
+        .on_http(args.eth_rpc_url.clone());
+    let contract = CartesiLidoOracle::new(args.contract_address, provider);
 
-        if status != 200 {
-            tracing::debug!("Preimage check response: {:?}", &text);
-            return Err(anyhow::anyhow!("Check showed preimages not uploaded"));
+    tracing::info!("Initiating report with onchain transaction");
+    if args.trusted {
+        let genesis_block_timestamp = contract.genesis_block_timestamp().call().await?._0;
+        if genesis_block_timestamp.is_zero() {
+            bail!("Contract has no genesis timestamp set so cannot look up beacon roots");
+        }
+        // check the chain will supply the root the inputs were built for before paying for
+        // a coprocessor run that would fail
+        let onchain_root = find_block_root(
+            contract.provider(),
+            genesis_block_timestamp.try_into()?,
+            slot,
+        )
+        .await?;
+        if onchain_root != block_root {
+            bail!(
+                "Beacon roots contract has root {} for slot {} but the inputs were built for {}",
+                onchain_root,
+                slot,
+                block_root
+            );
         }
+        Ok(contract
+            .generateReport(U256::from(slot), manifest_hash)
+            .send()
+            .await?)
+    } else {
+        Ok(contract
+            .generateReportUntrusted(U256::from(slot), block_root, manifest_hash)
+            .send()
+            .await?)
     }
-
-    Ok(())
 }
@@ -0,0 +1,357 @@
+//! Client for the HTTP API of a Cartesi coprocessor operator and its solver
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+use url::Url;
+
+/// A preimage as expected by the operator: (hash type, hash, data)
+pub type Preimage = (u8, Vec<u8>, Vec<u8>);
+
+#[derive(Error, Debug)]
+pub enum OperatorError {
+    #[error("request to the operator failed: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("invalid operator url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("failed to encode request body: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("operator responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+}
+
+impl OperatorError {
+    /// Whether the request may succeed if tried again
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OperatorError::Reqwest(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            OperatorError::Status { status, .. } => status.is_server_error(),
+            OperatorError::Url(_) | OperatorError::Cbor(_) => false,
+        }
+    }
+}
+
+/// Progress of a solver ensuring a machine is available to the operators
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnsureStatus {
+    Ready,
+    /// Not ready yet, with the solver's description of the current state
+    Pending(String),
+}
+
+/// JSON body of the solver's ensure response
+#[derive(Deserialize)]
+struct EnsureResponse {
+    state: String,
+}
+
+impl EnsureStatus {
+    /// Parse the ensure response body. This is a JSON object with a `state` field, or the bare
+    /// state from older solvers. Only a state of exactly `ready` is ready, so states such as
+    /// `not ready` are not mistaken for it
+    fn parse(body: &str) -> Self {
+        let state = match serde_json::from_str::<EnsureResponse>(body) {
+            Ok(response) => response.state,
+            Err(_) => body.trim().trim_matches('"').to_string(),
+        };
+        if state.eq_ignore_ascii_case("ready") {
+            EnsureStatus::Ready
+        } else {
+            EnsureStatus::Pending(body.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OperatorClient {
+    client: reqwest::Client,
+    base_url: Url,
+    /// Send one preimage per request for operators without the batched endpoints
+    legacy: bool,
+}
+
+impl OperatorClient {
+    pub fn new(base_url: Url) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            legacy: false,
+        }
+    }
+
+    pub fn with_legacy(mut self, legacy: bool) -> Self {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Check the operator is up
+    pub async fn health(&self) -> Result<(), OperatorError> {
+        let res = self
+            .client
+            .get(self.base_url.join("/health")?)
+            .send()
+            .await?;
+        check_response(res).await?;
+        Ok(())
+    }
+
+    /// Upload preimages so they can be retrieved by the machine through the preimage oracle.
+    /// In legacy mode each preimage is sent in its own request
+    pub async fn upload_preimages(&self, preimages: &[Preimage]) -> Result<(), OperatorError> {
+        if self.legacy {
+            for preimage in preimages {
+                self.post("/upload_preimages/", serde_cbor::to_vec(preimage)?)
+                    .await?;
+            }
+        } else {
+            self.post("/upload_preimages/", serde_cbor::to_vec(&preimages)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the operator holds all of the preimages, given as (hash type, hash).
+    /// In legacy mode each preimage is checked in its own request
+    pub async fn check_preimage_status(
+        &self,
+        hashes: &[(u8, Vec<u8>)],
+    ) -> Result<bool, OperatorError> {
+        if self.legacy {
+            for hash in hashes {
+                if !self.check(serde_cbor::to_vec(hash)?).await? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        } else {
+            self.check(serde_cbor::to_vec(&hashes)?).await
+        }
+    }
+
+    /// Which of the preimages, given as (hash type, hash), the operator holds. The status endpoint
+    /// only says whether it holds all of a request so each preimage is checked in its own request
+    pub async fn preimages_held(
+        &self,
+        hashes: &[(u8, Vec<u8>)],
+    ) -> Result<Vec<bool>, OperatorError> {
+        let mut held = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            held.push(
+                self.check_preimage_status(std::slice::from_ref(hash))
+                    .await?,
+            );
+        }
+        Ok(held)
+    }
+
+    /// Ask the solver to make the machine with the given hash available to operators, fetching it
+    /// from IPFS by its CID. This is served by the solver rather than the operator so the client
+    /// must be created with the solver url
+    pub async fn ensure(
+        &self,
+        cid: &str,
+        machine_hash: &str,
+        size: u64,
+    ) -> Result<EnsureStatus, OperatorError> {
+        let url = self
+            .base_url
+            .join(&format!("/ensure/{}/{}/{}", cid, machine_hash, size))?;
+        let res = self.client.post(url).send().await?;
+        let body = check_response(res).await?;
+        Ok(EnsureStatus::parse(&body))
+    }
+
+    async fn post(&self, path: &str, body: Vec<u8>) -> Result<String, OperatorError> {
+        let res = self
+            .client
+            .post(self.base_url.join(path)?)
+            .body(body)
+            .send()
+            .await?;
+        check_response(res).await
+    }
+
+    async fn check(&self, body: Vec<u8>) -> Result<bool, OperatorError> {
+        let res = self
+            .client
+            .post(self.base_url.join("/check_preimages_status/")?)
+            .body(body)
+            .send()
+            .await?;
+        match check_response(res).await {
+            Ok(_) => Ok(true),
+            // server errors say nothing about whether the preimages are held
+            Err(OperatorError::Status { status, body }) if !status.is_server_error() => {
+                tracing::debug!("Preimage check response: {:?}", body);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Return the response body if the status is 200, otherwise an error
+async fn check_response(res: reqwest::Response) -> Result<String, OperatorError> {
+    let status = res.status();
+    let body = res.text().await?;
+    if status != StatusCode::OK {
+        return Err(OperatorError::Status { status, body });
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_bytes, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn preimages() -> Vec<Preimage> {
+        vec![
+            (2, vec![1; 32], vec![1, 2, 3]),
+            (2, vec![2; 32], vec![4, 5]),
+        ]
+    }
+
+    fn client(server: &MockServer) -> OperatorClient {
+        OperatorClient::new(server.uri().parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn uploads_preimages_in_one_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .and(body_bytes(serde_cbor::to_vec(&preimages()).unwrap()))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server)
+            .upload_preimages(&preimages())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn legacy_uploads_each_preimage() {
+        let server = MockServer::start().await;
+        for preimage in preimages() {
+            Mock::given(method("POST"))
+                .and(path("/upload_preimages/"))
+                .and(body_bytes(serde_cbor::to_vec(&preimage).unwrap()))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        client(&server)
+            .with_legacy(true)
+            .upload_preimages(&preimages())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn checks_preimage_status() {
+        let hashes = vec![(2, vec![1; 32])];
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .respond_with(ResponseTemplate::new(200))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("missing"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("busy"))
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        assert!(client.check_preimage_status(&hashes).await.unwrap());
+        assert!(!client.check_preimage_status(&hashes).await.unwrap());
+        let err = client.check_preimage_status(&hashes).await.unwrap_err();
+        assert!(err.is_retryable());
+        assert!(
+            matches!(err, OperatorError::Status { status, body } if status == 503 && body == "busy")
+        );
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retryable() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let err = client(&server)
+            .upload_preimages(&preimages())
+            .await
+            .unwrap_err();
+        assert!(!err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn ensure_reports_ready_only_when_ready() {
+        let server = MockServer::start().await;
+        for body in [
+            r#"{"state":"downloading"}"#,
+            "not ready",
+            r#"{"state":"ready"}"#,
+        ] {
+            Mock::given(method("POST"))
+                .and(path("/ensure/cid/0xhash/42"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+
+        let client = client(&server);
+        assert_eq!(
+            client.ensure("cid", "0xhash", 42).await.unwrap(),
+            EnsureStatus::Pending(r#"{"state":"downloading"}"#.to_string())
+        );
+        assert_eq!(
+            client.ensure("cid", "0xhash", 42).await.unwrap(),
+            EnsureStatus::Pending("not ready".to_string())
+        );
+        assert_eq!(
+            client.ensure("cid", "0xhash", 42).await.unwrap(),
+            EnsureStatus::Ready
+        );
+    }
+
+    #[test]
+    fn parses_ensure_status() {
+        assert_eq!(EnsureStatus::parse("ready"), EnsureStatus::Ready);
+        assert_eq!(EnsureStatus::parse("\"ready\"\n"), EnsureStatus::Ready);
+        assert_eq!(
+            EnsureStatus::parse(r#"{"state": "ready"}"#),
+            EnsureStatus::Ready
+        );
+        assert!(matches!(
+            EnsureStatus::parse("not ready"),
+            EnsureStatus::Pending(_)
+        ));
+        assert!(matches!(
+            EnsureStatus::parse(r#"{"state": "not_ready"}"#),
+            EnsureStatus::Pending(_)
+        ));
+        assert!(matches!(EnsureStatus::parse(""), EnsureStatus::Pending(_)));
+    }
+}
@@ -0,0 +1,117 @@
+//! Decoding beacon states and blocks from SSZ, such as local checkpoint sync dumps
+//!
+//! Files may be raw SSZ, as served by the beacon API, or snappy framed as on the p2p network. SSZ
+//! data does not say which fork it belongs to so this is worked out from the slot, which is at a
+//! fixed offset in every fork, and the network's fork schedule.
+
+use std::{io::Read, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use ethereum_consensus::{
+    altair, bellatrix, capella, deneb, electra, phase0,
+    phase0::SignedBeaconBlockHeader,
+    types::mainnet::{BeaconState, SignedBeaconBlock},
+    Fork,
+};
+use io::NetworkConfig;
+use ssz_rs::prelude::*;
+
+/// Stream identifier chunk that starts every snappy framed stream
+const SNAPPY_STREAM_IDENTIFIER: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+
+/// Offset of the slot in a beacon state, after `genesis_time` and `genesis_validators_root`
+const STATE_SLOT_OFFSET: usize = 40;
+
+/// Offset of the slot in a signed beacon block, after the offset of the message and the signature
+const BLOCK_SLOT_OFFSET: usize = 100;
+
+/// Read a file, decompressing it if it is snappy framed
+pub fn read_ssz_file(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+    if !data.starts_with(SNAPPY_STREAM_IDENTIFIER) {
+        return Ok(data);
+    }
+    let mut decompressed = Vec::new();
+    snap::read::FrameDecoder::new(data.as_slice())
+        .read_to_end(&mut decompressed)
+        .with_context(|| format!("failed to decompress {:?}", path))?;
+    Ok(decompressed)
+}
+
+/// Load a beacon state, decoding it as the fork active at its slot
+pub fn load_state(path: &Path, network: &NetworkConfig) -> Result<BeaconState> {
+    let data = read_ssz_file(path)?;
+    let slot = read_slot(&data, STATE_SLOT_OFFSET)?;
+    let fork = network.fork_at_slot(slot);
+    tracing::info!("Decoding {:?} as a {} state at slot {}", path, fork, slot);
+    Ok(decode_state(&data, fork)?)
+}
+
+/// Decode a beacon state of the given fork
+pub fn decode_state(data: &[u8], fork: Fork) -> Result<BeaconState, DeserializeError> {
+    let state = match fork {
+        Fork::Phase0 => BeaconState::Phase0(phase0::mainnet::BeaconState::deserialize(data)?),
+        Fork::Altair => BeaconState::Altair(altair::mainnet::BeaconState::deserialize(data)?),
+        Fork::Bellatrix => {
+            BeaconState::Bellatrix(bellatrix::mainnet::BeaconState::deserialize(data)?)
+        }
+        Fork::Capella => BeaconState::Capella(capella::mainnet::BeaconState::deserialize(data)?),
+        Fork::Deneb => BeaconState::Deneb(deneb::mainnet::BeaconState::deserialize(data)?),
+        Fork::Electra => BeaconState::Electra(electra::mainnet::BeaconState::deserialize(data)?),
+    };
+    Ok(state)
+}
+
+/// Load a signed beacon block, decoding it as the fork active at its slot
+pub fn load_block(path: &Path, network: &NetworkConfig) -> Result<SignedBeaconBlock> {
+    let data = read_ssz_file(path)?;
+    let slot = read_slot(&data, BLOCK_SLOT_OFFSET)?;
+    let fork = network.fork_at_slot(slot);
+    tracing::info!("Decoding {:?} as a {} block at slot {}", path, fork, slot);
+    Ok(decode_block(&data, fork)?)
+}
+
+/// Decode a signed beacon block of the given fork
+pub fn decode_block(data: &[u8], fork: Fork) -> Result<SignedBeaconBlock, DeserializeError> {
+    let block = match fork {
+        Fork::Phase0 => {
+            SignedBeaconBlock::Phase0(phase0::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+        Fork::Altair => {
+            SignedBeaconBlock::Altair(altair::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+        Fork::Bellatrix => {
+            SignedBeaconBlock::Bellatrix(bellatrix::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+        Fork::Capella => {
+            SignedBeaconBlock::Capella(capella::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+        Fork::Deneb => {
+            SignedBeaconBlock::Deneb(deneb::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+        Fork::Electra => {
+            SignedBeaconBlock::Electra(electra::mainnet::SignedBeaconBlock::deserialize(data)?)
+        }
+    };
+    Ok(block)
+}
+
+/// Load a signed beacon block header. This is the same in every fork
+pub fn load_header(path: &Path) -> Result<SignedBeaconBlockHeader> {
+    Ok(SignedBeaconBlockHeader::deserialize(&read_ssz_file(path)?)?)
+}
+
+/// Parse a fork name as used in the `Eth-Consensus-Version` header, e.g. `deneb`
+pub fn parse_fork(name: &str) -> Option<Fork> {
+    serde_json::from_value(serde_json::Value::String(name.trim().to_lowercase())).ok()
+}
+
+fn read_slot(data: &[u8], offset: usize) -> Result<u64> {
+    ensure!(
+        data.len() >= offset + 8,
+        "SSZ data is too short to contain a slot"
+    );
+    Ok(u64::from_le_bytes(
+        data[offset..offset + 8].try_into().unwrap(),
+    ))
+}
@@ -0,0 +1,314 @@
+//! Uploading preimages to a coprocessor operator
+//!
+//! Preimages are uploaded in batches with a bounded number of requests in flight. Before each
+//! batch is uploaded the operator is asked which of its preimages it already holds and only the
+//! missing ones are sent. Preimages are listed in manifest order so the batches are the same on
+//! every run, which makes an interrupted upload resumable by simply running it again.
+
+use std::{future::Future, time::Duration};
+
+use anyhow::{bail, Result};
+use futures::{stream, StreamExt, TryStreamExt};
+
+use crate::operator_client::{OperatorClient, OperatorError, Preimage};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct UploadOptions {
+    /// Number of preimages to send in each upload and status request
+    #[clap(long, env, default_value_t = 16)]
+    pub upload_batch_size: usize,
+
+    /// Maximum number of upload requests in flight at once
+    #[clap(long, env, default_value_t = 4)]
+    pub upload_concurrency: usize,
+
+    /// Number of times to retry a failed request, backing off exponentially between attempts
+    #[clap(long, env, default_value_t = 5)]
+    pub upload_retries: u32,
+
+    /// Send one preimage per request for operators without the batched endpoints
+    #[clap(long, env)]
+    pub legacy_upload: bool,
+}
+
+impl UploadOptions {
+    fn batch_size(&self) -> usize {
+        // legacy operators take one preimage per request anyway so check and skip them individually
+        if self.legacy_upload {
+            1
+        } else {
+            self.upload_batch_size.max(1)
+        }
+    }
+}
+
+/// Upload the preimages to the operator, skipping any it already holds,
+/// then check they are all available
+pub async fn upload_preimages(
+    operator: &OperatorClient,
+    preimages: &[Preimage],
+    options: &UploadOptions,
+) -> Result<()> {
+    if let Err(e) = operator.health().await {
+        tracing::warn!("Operator health check failed: {}", e);
+    }
+
+    let batches: Vec<&[Preimage]> = preimages.chunks(options.batch_size()).collect();
+
+    let uploaded: Vec<usize> = stream::iter(batches.iter())
+        .map(|batch| upload_batch(operator, batch, options))
+        .buffer_unordered(options.upload_concurrency.max(1))
+        .try_collect()
+        .await?;
+    tracing::info!(
+        "Uploaded {} of {} preimages, the rest were already held by the operator",
+        uploaded.iter().sum::<usize>(),
+        preimages.len()
+    );
+
+    // sanity check the preimages are uploaded correctly
+    let present: Vec<bool> = stream::iter(batches.iter())
+        .map(|batch| {
+            let hashes = hashes(batch);
+            async move {
+                with_retries(options.upload_retries, || {
+                    operator.check_preimage_status(&hashes)
+                })
+                .await
+            }
+        })
+        .buffer_unordered(options.upload_concurrency.max(1))
+        .try_collect()
+        .await?;
+    if present.iter().any(|p| !p) {
+        bail!("Check showed preimages not uploaded");
+    }
+
+    Ok(())
+}
+
+/// Upload the preimages of a batch the operator does not hold. Returns the number uploaded
+async fn upload_batch(
+    operator: &OperatorClient,
+    batch: &[Preimage],
+    options: &UploadOptions,
+) -> Result<usize, OperatorError> {
+    let hashes = hashes(batch);
+    if with_retries(options.upload_retries, || {
+        operator.check_preimage_status(&hashes)
+    })
+    .await?
+    {
+        return Ok(0);
+    }
+
+    // the batch check is all or nothing so find out which preimages are missing
+    let missing: Vec<Preimage> = if batch.len() == 1 {
+        batch.to_vec()
+    } else {
+        let held =
+            with_retries(options.upload_retries, || operator.preimages_held(&hashes)).await?;
+        batch
+            .iter()
+            .zip(held)
+            .filter(|(_, held)| !held)
+            .map(|(preimage, _)| preimage.clone())
+            .collect()
+    };
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    with_retries(options.upload_retries, || {
+        operator.upload_preimages(&missing)
+    })
+    .await?;
+    Ok(missing.len())
+}
+
+fn hashes(batch: &[Preimage]) -> Vec<(u8, Vec<u8>)> {
+    batch
+        .iter()
+        .map(|(hash_type, hash, _)| (*hash_type, hash.clone()))
+        .collect()
+}
+
+/// Run the request, retrying retryable errors with exponential backoff
+async fn with_retries<T, F, Fut>(retries: u32, mut request: F) -> Result<T, OperatorError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OperatorError>>,
+{
+    let mut delay = Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries && e.is_retryable() => {
+                attempt += 1;
+                tracing::warn!(
+                    "Request failed, retrying in {:?} ({}/{}): {}",
+                    delay,
+                    attempt,
+                    retries,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_bytes, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn options(upload_retries: u32) -> UploadOptions {
+        UploadOptions {
+            upload_batch_size: 16,
+            upload_concurrency: 1,
+            upload_retries,
+            legacy_upload: false,
+        }
+    }
+
+    async fn mock_status(server: &MockServer, status: u16, times: Option<u64>) {
+        let mock = Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .respond_with(ResponseTemplate::new(status));
+        match times {
+            Some(times) => mock.up_to_n_times(times).mount(server).await,
+            None => mock.mount(server).await,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_then_uploads() {
+        let server = MockServer::start().await;
+        // the first check fails, its retry finds the batch missing, and the final check finds it
+        mock_status(&server, 500, Some(1)).await;
+        mock_status(&server, 404, Some(1)).await;
+        mock_status(&server, 200, None).await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let operator = OperatorClient::new(server.uri().parse().unwrap());
+        let preimages = vec![(2, vec![1; 32], vec![1, 2, 3])];
+        upload_preimages(&operator, &preimages, &options(2))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_batches_the_operator_holds() {
+        let server = MockServer::start().await;
+        mock_status(&server, 200, None).await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let operator = OperatorClient::new(server.uri().parse().unwrap());
+        let preimages = vec![(2, vec![1; 32], vec![1, 2, 3])];
+        upload_preimages(&operator, &preimages, &options(0))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_only_the_missing_preimages_of_a_batch() {
+        let server = MockServer::start().await;
+        let preimages: Vec<Preimage> = (1..=3).map(|i| (2, vec![i; 32], vec![i])).collect();
+        let batch = serde_cbor::to_vec(&hashes(&preimages)).unwrap();
+        Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .and(body_bytes(batch.clone()))
+            .respond_with(ResponseTemplate::new(404))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/check_preimages_status/"))
+            .and(body_bytes(batch))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        for (preimage, status) in preimages.iter().zip([200, 404, 200]) {
+            Mock::given(method("POST"))
+                .and(path("/check_preimages_status/"))
+                .and(body_bytes(
+                    serde_cbor::to_vec(&hashes(std::slice::from_ref(preimage))).unwrap(),
+                ))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .and(body_bytes(serde_cbor::to_vec(&preimages[1..2]).unwrap()))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let operator = OperatorClient::new(server.uri().parse().unwrap());
+        upload_preimages(&operator, &preimages, &options(0))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        mock_status(&server, 404, None).await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let operator = OperatorClient::new(server.uri().parse().unwrap());
+        let preimages = vec![(2, vec![1; 32], vec![1, 2, 3])];
+        assert!(upload_preimages(&operator, &preimages, &options(3))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_if_preimages_are_missing_after_upload() {
+        let server = MockServer::start().await;
+        mock_status(&server, 404, None).await;
+        Mock::given(method("POST"))
+            .and(path("/upload_preimages/"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let operator = OperatorClient::new(server.uri().parse().unwrap());
+        let preimages = vec![(2, vec![1; 32], vec![1, 2, 3])];
+        assert!(upload_preimages(&operator, &preimages, &options(0))
+            .await
+            .is_err());
+    }
+}
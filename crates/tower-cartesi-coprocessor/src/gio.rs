@@ -0,0 +1,270 @@
+//! Client for the Generic I/O (GIO) interface of the rollup HTTP server
+//!
+//! GIO lets a program inside the machine request data from the host, such as the preimages
+//! of hashes that have been uploaded to the coprocessor operator.
+
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use thiserror::Error;
+
+/// Response code returned by the host when a GIO request succeeds
+pub const GIO_RESPONSE_SUCCESS: u16 = 0;
+
+/// GIO domains understood by the coprocessor operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GioDomain {
+    /// Retrieve the preimage of a hash. The id is the hash prefixed by its [HashType]
+    Preimage,
+    /// Any other domain, by its code
+    Other(u16),
+}
+
+impl GioDomain {
+    pub fn code(&self) -> u16 {
+        match self {
+            GioDomain::Preimage => 0x2a,
+            GioDomain::Other(code) => *code,
+        }
+    }
+}
+
+/// Hash functions the preimage oracle can look up preimages by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashType {
+    Sha256,
+    Keccak256,
+}
+
+impl HashType {
+    /// The byte the hash is prefixed with in a preimage request id
+    pub fn prefix(&self) -> u8 {
+        match self {
+            HashType::Sha256 => 0x01,
+            HashType::Keccak256 => 0x02,
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> [u8; 32] {
+        match self {
+            HashType::Sha256 => Sha256::digest(data).into(),
+            HashType::Keccak256 => Keccak256::digest(data).into(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum GioError {
+    #[error("GIO request failed: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("GIO response is not valid hex: {0}")]
+    HexParseError(#[from] hex::FromHexError),
+    #[error("GIO request to domain {domain:#x} failed with response code {code}")]
+    ResponseCode { domain: u16, code: u16 },
+    #[error("preimage does not match the requested {hash_type:?} hash 0x{hash}")]
+    HashMismatch { hash_type: HashType, hash: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GioRequest {
+    domain: u16,
+    id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GioResponse {
+    response_code: u16,
+    response: String,
+}
+
+/// Makes GIO requests to the rollup HTTP server.
+/// Cloning is cheap and clones share the same connection pool
+#[derive(Debug, Clone)]
+pub struct GioClient {
+    client: reqwest::Client,
+    host_uri: String,
+}
+
+impl GioClient {
+    pub fn new(host_uri: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            host_uri: host_uri.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Make a request to a domain, returning the response data
+    pub async fn request(&self, domain: GioDomain, id: &[u8]) -> Result<Vec<u8>, GioError> {
+        let request = GioRequest {
+            domain: domain.code(),
+            id: format!("0x{}", hex::encode(id)),
+        };
+        tracing::debug!("Sending GIO request: {:?}", request);
+        let response: GioResponse = self
+            .client
+            .post(format!("{}/gio", self.host_uri))
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if response.response_code != GIO_RESPONSE_SUCCESS {
+            return Err(GioError::ResponseCode {
+                domain: request.domain,
+                code: response.response_code,
+            });
+        }
+        Ok(hex::decode(response.response.trim_start_matches("0x"))?)
+    }
+
+    /// Retrieve the preimage of a hash, checking the returned data actually hashes to it.
+    /// Attempting to retrieve a preimage that has not been uploaded to the operator will halt execution
+    pub async fn get_preimage(
+        &self,
+        hash_type: HashType,
+        hash: [u8; 32],
+    ) -> Result<Vec<u8>, GioError> {
+        let mut id = Vec::with_capacity(33);
+        id.push(hash_type.prefix());
+        id.extend_from_slice(&hash);
+
+        let preimage = self.request(GioDomain::Preimage, &id).await?;
+        if hash_type.hash(&preimage) != hash {
+            return Err(GioError::HashMismatch {
+                hash_type,
+                hash: hex::encode(hash),
+            });
+        }
+        Ok(preimage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    const PREIMAGE: &[u8] = b"preimage data";
+
+    /// Respond to a preimage request for `hash` with a response code and data
+    async fn mock_preimage(
+        server: &MockServer,
+        hash_type: HashType,
+        hash: [u8; 32],
+        code: u16,
+        data: &[u8],
+    ) {
+        Mock::given(method("POST"))
+            .and(path("/gio"))
+            .and(body_json(json!({
+                "domain": 0x2a,
+                "id": format!("0x{:02x}{}", hash_type.prefix(), hex::encode(hash)),
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "response_code": code,
+                "response": format!("0x{}", hex::encode(data)),
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn retrieves_preimages() {
+        let server = MockServer::start().await;
+        for hash_type in [HashType::Keccak256, HashType::Sha256] {
+            let hash = hash_type.hash(PREIMAGE);
+            mock_preimage(&server, hash_type, hash, GIO_RESPONSE_SUCCESS, PREIMAGE).await;
+            let preimage = GioClient::new(&format!("{}/", server.uri()))
+                .get_preimage(hash_type, hash)
+                .await
+                .unwrap();
+            assert_eq!(preimage, PREIMAGE);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_non_accept_response_codes() {
+        let server = MockServer::start().await;
+        let hash = HashType::Keccak256.hash(PREIMAGE);
+        mock_preimage(&server, HashType::Keccak256, hash, 3, &[]).await;
+
+        let err = GioClient::new(&server.uri())
+            .get_preimage(HashType::Keccak256, hash)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GioError::ResponseCode {
+                    domain: 0x2a,
+                    code: 3
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_preimages_that_do_not_match_the_hash() {
+        let server = MockServer::start().await;
+        let hash = HashType::Keccak256.hash(PREIMAGE);
+        mock_preimage(
+            &server,
+            HashType::Keccak256,
+            hash,
+            GIO_RESPONSE_SUCCESS,
+            b"other data",
+        )
+        .await;
+
+        let err = GioClient::new(&server.uri())
+            .get_preimage(HashType::Keccak256, hash)
+            .await
+            .unwrap_err();
+        match err {
+            GioError::HashMismatch {
+                hash_type,
+                hash: hex_hash,
+            } => {
+                assert_eq!(hash_type, HashType::Keccak256);
+                assert_eq!(hex_hash, hex::encode(hash));
+            }
+            err => panic!("expected a hash mismatch, got {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn surfaces_http_and_encoding_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/gio"))
+            .and(body_json(json!({ "domain": 1, "id": "0x01" })))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/gio"))
+            .and(body_json(json!({ "domain": 1, "id": "0x02" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "response_code": 0,
+                "response": "0xnot hex",
+            })))
+            .mount(&server)
+            .await;
+
+        let client = GioClient::new(&server.uri());
+        let err = client.request(GioDomain::Other(1), &[1]).await.unwrap_err();
+        assert!(matches!(err, GioError::Reqwest(_)), "{:?}", err);
+        let err = client.request(GioDomain::Other(1), &[2]).await.unwrap_err();
+        assert!(matches!(err, GioError::HexParseError(_)), "{:?}", err);
+    }
+}
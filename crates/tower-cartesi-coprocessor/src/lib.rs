@@ -3,10 +3,12 @@ use thiserror::Error;
 use tokio::time::interval;
 use tower_service::Service;
 
+mod gio;
 mod messages;
 mod request;
 mod response;
 
+pub use gio::{GioClient, GioDomain, GioError, HashType, GIO_RESPONSE_SUCCESS};
 pub use messages::{AdvanceStateMetadata, Output};
 pub use request::Request;
 pub use response::Response;
@@ -36,6 +36,12 @@ impl Response {
         });
     }
 
+    pub fn add_report(&mut self, payload: &[u8]) {
+        self.outputs.push(Output::Report {
+            payload: payload.to_vec(),
+        });
+    }
+
     pub fn finish_message(&self) -> Finish {
         match self.status {
             Status::Accept => Finish::accept(),
@@ -0,0 +1,177 @@
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const MAX_CHUNK_SIZE: u64 = 1000;
+
+    fn chunks(count: usize) -> Vec<Chunk> {
+        (0..count)
+            .map(|i| Chunk {
+                hash: keccak256((i as u64).to_le_bytes()).0,
+                length: 1 + (i as u64 * 37) % MAX_CHUNK_SIZE,
+            })
+            .collect()
+    }
+
+    fn length(chunks: &[Chunk]) -> u64 {
+        chunks.iter().map(|c| c.length).sum()
+    }
+
+    /// Walk the tree as the coprocessor does, loading and validating each node as it is reached.
+    /// Returns the chunks in order along with the depth of the tree
+    fn walk(root: &ChunkIndex, preimages: &[Vec<u8>]) -> Result<(Vec<Chunk>, usize)> {
+        let nodes: HashMap<[u8; 32], &Vec<u8>> = preimages
+            .iter()
+            .map(|node| (keccak256(node).0, node))
+            .collect();
+        let mut chunks = Vec::new();
+        let mut depth = 0;
+        // each entry is a node to visit and its depth
+        let mut pending: Vec<(IndexRef, usize)> = Vec::new();
+        let mut node = (root.clone(), 1);
+        loop {
+            depth = depth.max(node.1);
+            match node.0 {
+                ChunkIndex::Chunks(c) => chunks.extend(c),
+                ChunkIndex::Nodes(children) => {
+                    pending.extend(children.into_iter().rev().map(|child| (child, node.1 + 1)))
+                }
+            }
+            let Some((child, child_depth)) = pending.pop() else {
+                break;
+            };
+            let bytes = nodes
+                .get(&child.hash)
+                .ok_or_else(|| anyhow!("missing index node"))?;
+            let child_node = ChunkIndex::from_bytes(bytes)?;
+            child_node.validate(child.length, MAX_CHUNK_SIZE)?;
+            node = (child_node, child_depth);
+        }
+        Ok((chunks, depth))
+    }
+
+    #[test]
+    fn walks_a_tree_of_several_levels() {
+        // with a fanout of 4, 70 chunks are listed in 18 leaves under 5 nodes under 2 nodes
+        let chunks = chunks(70);
+        let (root, preimages) = ChunkIndex::build_with_fanout(chunks.clone(), 4).unwrap();
+        assert_eq!(root.len(), 2);
+        assert_eq!(preimages.len(), 18 + 5 + 2);
+        root.validate(length(&chunks), MAX_CHUNK_SIZE).unwrap();
+
+        let (walked, depth) = walk(&root, &preimages).unwrap();
+        assert_eq!(walked, chunks);
+        assert_eq!(depth, 4);
+    }
+
+    #[test]
+    fn splits_at_the_maximum_fanout() {
+        let fits = chunks(MAX_INDEX_FANOUT);
+        let (root, preimages) = ChunkIndex::build(fits.clone()).unwrap();
+        assert_eq!(root, ChunkIndex::Chunks(fits));
+        assert!(preimages.is_empty());
+
+        let chunks = chunks(2 * MAX_INDEX_FANOUT + 3);
+        let (root, preimages) = ChunkIndex::build(chunks.clone()).unwrap();
+        assert!(matches!(&root, ChunkIndex::Nodes(refs) if refs.len() == 3));
+        assert_eq!(preimages.len(), 3);
+        root.validate(length(&chunks), MAX_CHUNK_SIZE).unwrap();
+        assert_eq!(walk(&root, &preimages).unwrap(), (chunks, 2));
+    }
+
+    #[test]
+    fn single_chunk_section() {
+        let chunks = chunks(1);
+        let (root, preimages) = ChunkIndex::build(chunks.clone()).unwrap();
+        assert_eq!(root, ChunkIndex::Chunks(chunks.clone()));
+        assert!(preimages.is_empty());
+
+        let mut manifest = Manifest::new(MAX_CHUNK_SIZE, "mainnet".to_string(), StateEncoding::Full);
+        manifest.add_section(Section {
+            kind: SectionKind::BlockHeader,
+            encoding: Encoding::Ssz,
+            compression: Compression::None,
+            length: length(&chunks),
+            index: root,
+        });
+        let decoded = Manifest::from_bytes(&manifest.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.sections, manifest.sections);
+    }
+
+    #[test]
+    fn validate_rejects_bad_lengths() {
+        let chunks = chunks(10);
+        let root = ChunkIndex::Chunks(chunks.clone());
+        let total = length(&chunks);
+        root.validate(total, MAX_CHUNK_SIZE).unwrap();
+        assert!(root.validate(total - 1, MAX_CHUNK_SIZE).is_err());
+        assert!(root.validate(total + 1, MAX_CHUNK_SIZE).is_err());
+
+        let mut empty = chunks.clone();
+        empty[3].length = 0;
+        assert!(ChunkIndex::Chunks(empty)
+            .validate(total - chunks[3].length, MAX_CHUNK_SIZE)
+            .is_err());
+
+        let mut oversized = chunks.clone();
+        oversized[3].length = MAX_CHUNK_SIZE + 1;
+        let oversized_total = total - chunks[3].length + MAX_CHUNK_SIZE + 1;
+        assert!(ChunkIndex::Chunks(oversized)
+            .validate(oversized_total, MAX_CHUNK_SIZE)
+            .is_err());
+
+        let too_wide = self::chunks(MAX_INDEX_FANOUT + 1);
+        assert!(ChunkIndex::Chunks(too_wide.clone())
+            .validate(length(&too_wide), MAX_CHUNK_SIZE)
+            .is_err());
+    }
+
+    #[test]
+    fn walk_rejects_child_refs_with_the_wrong_length() {
+        let chunks = chunks(20);
+        let (root, preimages) = ChunkIndex::build_with_fanout(chunks.clone(), 4).unwrap();
+        let ChunkIndex::Nodes(mut refs) = root else {
+            panic!("expected index nodes");
+        };
+        // move bytes from one child to the next so the root still covers the section but the
+        // children no longer start where their refs say they do
+        refs[0].length -= 1;
+        refs[1].length += 1;
+        let root = ChunkIndex::Nodes(refs);
+        root.validate(length(&chunks), MAX_CHUNK_SIZE).unwrap();
+        assert!(walk(&root, &preimages).is_err());
+    }
+
+    #[test]
+    fn manifest_rejects_inconsistent_sections() {
+        let chunks = chunks(3);
+        let section = Section {
+            kind: SectionKind::State,
+            encoding: Encoding::Ssz,
+            compression: Compression::Zstd,
+            length: length(&chunks),
+            index: ChunkIndex::Chunks(chunks),
+        };
+        let new = || Manifest::new(MAX_CHUNK_SIZE, "mainnet".to_string(), StateEncoding::Full);
+
+        let mut wrong_section_length = new();
+        wrong_section_length.add_section(Section {
+            length: section.length + 1,
+            ..section.clone()
+        });
+        assert!(Manifest::from_bytes(&wrong_section_length.to_bytes().unwrap()).is_err());
+
+        let mut wrong_total = new();
+        wrong_total.add_section(section.clone());
+        wrong_total.total_length += 1;
+        assert!(Manifest::from_bytes(&wrong_total.to_bytes().unwrap()).is_err());
+
+        let mut duplicate = new();
+        duplicate.add_section(section.clone());
+        duplicate.add_section(section);
+        assert!(Manifest::from_bytes(&duplicate.to_bytes().unwrap()).is_err());
+    }
+}